@@ -0,0 +1,57 @@
+//! Additive oscillator summing sine partials
+
+use super::oscillator::Oscillator;
+use std::f32::consts::TAU;
+use std::sync::Arc;
+
+/// Sums harmonic partials; `amplitudes[0]` is the fundamental.
+///
+/// Partials above the Nyquist frequency are dropped for each note so high
+/// notes do not alias.
+#[derive(Debug, Clone)]
+pub struct Additive {
+    amplitudes: Arc<[f32]>,
+    partials: usize,
+    scale: f32,
+}
+
+impl Additive {
+    pub fn new(amplitudes: Arc<[f32]>) -> Self {
+        let partials = amplitudes.len();
+        let mut additive = Self {
+            amplitudes,
+            partials,
+            scale: 1.0,
+        };
+        additive.rescale();
+        additive
+    }
+
+    /// Keep the loudest possible sum at full scale
+    fn rescale(&mut self) {
+        let total: f32 = self.amplitudes[..self.partials]
+            .iter()
+            .map(|a| a.abs())
+            .sum();
+        self.scale = if total > 0.0 { 1.0 / total } else { 0.0 };
+    }
+}
+
+impl Oscillator for Additive {
+    fn start(&mut self, frequency: f32, sample_rate: u32) {
+        let nyquist = sample_rate as f32 / 2.0;
+        self.partials = ((nyquist / frequency) as usize)
+            .max(1)
+            .min(self.amplitudes.len());
+        self.rescale();
+    }
+
+    fn sample(&mut self, phase: f32) -> f32 {
+        let sum: f32 = self.amplitudes[..self.partials]
+            .iter()
+            .enumerate()
+            .map(|(k, a)| a * (TAU * (k + 1) as f32 * phase).sin())
+            .sum();
+        sum * self.scale
+    }
+}
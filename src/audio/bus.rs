@@ -0,0 +1,273 @@
+//! Master bus: sums every voice with its own gain, scales for headroom and
+//! runs the result through the effects chain and a soft limiter
+
+use super::{effects::EffectsChain, SAMPLE_RATE};
+use rodio::{
+    mixer::{self, Mixer, MixerSource},
+    OutputStream, Sink, Source,
+};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+const CHANNELS: u16 = 2;
+
+/// Limiter ceiling, just under full scale
+const THRESHOLD: f32 = 0.89; // -1 dBFS
+const HEADROOM_SMOOTHING: Duration = Duration::from_millis(20);
+const LIMITER_ATTACK: Duration = Duration::from_millis(1);
+const LIMITER_RELEASE: Duration = Duration::from_millis(100);
+
+/// The stereo mix every command plays (or renders) through
+pub struct MasterBus {
+    mixer: Mixer,
+    active: Arc<AtomicUsize>,
+}
+
+impl MasterBus {
+    /// A bus whose output never ends, for live playback
+    pub fn new(effects: &EffectsChain) -> (Self, BusOutput) {
+        Self::build(effects, true)
+    }
+
+    /// A bus whose output ends once every voice and effect tail has finished
+    pub fn offline(effects: &EffectsChain) -> (Self, BusOutput) {
+        Self::build(effects, false)
+    }
+
+    /// Create a live bus and start playing it on the output stream
+    pub fn connect(stream_handle: &OutputStream, effects: &EffectsChain) -> Self {
+        let (bus, output) = Self::new(effects);
+        stream_handle.mixer().add(output);
+        bus
+    }
+
+    fn build(effects: &EffectsChain, live: bool) -> (Self, BusOutput) {
+        let (mixer, source) = mixer::mixer(CHANNELS, SAMPLE_RATE);
+        let active = Arc::new(AtomicUsize::new(0));
+        let headroom = Headroom::new(source, active.clone());
+        let output = BusOutput {
+            input: effects.apply(headroom),
+            envelope: 0.0,
+            attack: decay_coefficient(LIMITER_ATTACK),
+            release: decay_coefficient(LIMITER_RELEASE),
+            live,
+        };
+        (Self { mixer, active }, output)
+    }
+
+    /// A sink feeding the bus, for voices that need to be stopped or waited on
+    pub fn sink(&self) -> Sink {
+        Sink::connect_new(&self.mixer)
+    }
+
+    /// Add a voice that plays until it ends
+    pub fn add<S>(&self, source: S, gain: f32)
+    where
+        S: Source + Send + 'static,
+    {
+        self.mixer.add(self.voice(source, gain));
+    }
+
+    /// Wrap a source as a bus voice so it counts toward the headroom while it plays
+    pub fn voice<S: Source>(&self, source: S, gain: f32) -> BusVoice<S> {
+        BusVoice {
+            input: source,
+            gain,
+            active: self.active.clone(),
+            state: VoiceState::Waiting,
+        }
+    }
+}
+
+fn decay_coefficient(time: Duration) -> f32 {
+    let samples = time.as_secs_f32() * SAMPLE_RATE as f32 * CHANNELS as f32;
+    (-1.0 / samples).exp()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VoiceState {
+    Waiting,
+    Playing,
+    Finished,
+}
+
+/// A source scaled by its voice gain and counted as active while it plays
+pub struct BusVoice<S> {
+    input: S,
+    gain: f32,
+    active: Arc<AtomicUsize>,
+    state: VoiceState,
+}
+
+impl<S> BusVoice<S> {
+    fn finish(&mut self) {
+        if self.state == VoiceState::Playing {
+            self.active.fetch_sub(1, Ordering::Relaxed);
+        }
+        self.state = VoiceState::Finished;
+    }
+}
+
+impl<S> Drop for BusVoice<S> {
+    fn drop(&mut self) {
+        // Stopped sinks drop their sources without draining them
+        self.finish();
+    }
+}
+
+impl<S: Source> Iterator for BusVoice<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.state == VoiceState::Waiting {
+            self.active.fetch_add(1, Ordering::Relaxed);
+            self.state = VoiceState::Playing;
+        }
+        match self.input.next() {
+            Some(sample) => Some(sample * self.gain),
+            None => {
+                self.finish();
+                None
+            }
+        }
+    }
+}
+
+impl<S: Source> Source for BusVoice<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        self.input.current_span_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+/// Scales the mix by 1/√n for n active voices, gliding between levels
+struct Headroom {
+    input: MixerSource,
+    active: Arc<AtomicUsize>,
+    gain: f32,
+    smoothing: f32,
+}
+
+impl Headroom {
+    fn new(input: MixerSource, active: Arc<AtomicUsize>) -> Self {
+        Self {
+            input,
+            active,
+            gain: 1.0,
+            smoothing: decay_coefficient(HEADROOM_SMOOTHING),
+        }
+    }
+}
+
+impl Iterator for Headroom {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.input.next()?;
+        let voices = self.active.load(Ordering::Relaxed).max(1);
+        let target = 1.0 / (voices as f32).sqrt();
+        self.gain = target + (self.gain - target) * self.smoothing;
+        Some(sample * self.gain)
+    }
+}
+
+impl Source for Headroom {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// The bus output: the effects chain followed by a soft limiter
+pub struct BusOutput {
+    input: Box<dyn Source + Send>,
+    envelope: f32,
+    attack: f32,
+    release: f32,
+    live: bool,
+}
+
+impl BusOutput {
+    /// Peak limiter, then a soft knee that catches whatever slips past its attack
+    fn limit(&mut self, sample: f32) -> f32 {
+        let level = sample.abs();
+        let coefficient = if level > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope = level + (self.envelope - level) * coefficient;
+        let limited = if self.envelope > THRESHOLD {
+            sample * THRESHOLD / self.envelope
+        } else {
+            sample
+        };
+        soft_clip(limited)
+    }
+}
+
+fn soft_clip(sample: f32) -> f32 {
+    let level = sample.abs();
+    if level <= THRESHOLD {
+        sample
+    } else {
+        let knee = 1.0 - THRESHOLD;
+        sample.signum() * (THRESHOLD + knee * ((level - THRESHOLD) / knee).tanh())
+    }
+}
+
+impl Iterator for BusOutput {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.input.next() {
+            Some(sample) => Some(self.limit(sample)),
+            // A live bus keeps playing silence until new voices arrive
+            None if self.live => Some(0.0),
+            None => None,
+        }
+    }
+}
+
+impl Source for BusOutput {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        CHANNELS
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
@@ -0,0 +1,143 @@
+//! Chorus: a short delay swept by a low-frequency oscillator
+
+use super::Tail;
+use rodio::Source;
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+/// Sweep rate, sweep depth, centre delay and wet level for a [`Chorus`]
+#[derive(Debug, Clone, Copy)]
+pub struct ChorusSettings {
+    pub rate: f32,
+    pub depth: Duration,
+    pub delay: Duration,
+    pub mix: f32,
+}
+
+impl Default for ChorusSettings {
+    fn default() -> Self {
+        Self {
+            rate: 0.8,
+            depth: Duration::from_millis(3),
+            delay: Duration::from_millis(15),
+            mix: 0.5,
+        }
+    }
+}
+
+impl ChorusSettings {
+    /// LFO rate in Hz
+    pub fn with_rate(mut self, rate: f32) -> Self {
+        self.rate = rate.max(0.0);
+        self
+    }
+
+    pub fn with_depth(mut self, depth: Duration) -> Self {
+        self.depth = depth.min(self.delay);
+        self
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn with_mix(mut self, mix: f32) -> Self {
+        self.mix = mix.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn tail(&self) -> Duration {
+        self.delay + self.depth
+    }
+}
+
+/// Chorus wrapped around a source
+pub struct Chorus<S> {
+    input: S,
+    settings: ChorusSettings,
+    channels: u16,
+    sample_rate: u32,
+    /// One delay line per channel
+    lines: Vec<Vec<f32>>,
+    write: usize,
+    channel: usize,
+    lfo_phase: f32,
+    tail: Tail,
+}
+
+impl<S: Source> Chorus<S> {
+    pub fn new(input: S, settings: ChorusSettings) -> Self {
+        let channels = input.channels();
+        let sample_rate = input.sample_rate();
+        let frames = (settings.tail().as_secs_f32() * sample_rate as f32) as usize + 2;
+        Self {
+            input,
+            settings,
+            channels,
+            sample_rate,
+            lines: vec![vec![0.0; frames]; channels as usize],
+            write: 0,
+            channel: 0,
+            lfo_phase: 0.0,
+            tail: Tail::new(settings.tail(), sample_rate, channels),
+        }
+    }
+
+    /// Linearly interpolated read `delay` samples behind the write head
+    fn read(&self, delay: f32) -> f32 {
+        let line = &self.lines[self.channel];
+        let position = (self.write as f32 - delay).rem_euclid(line.len() as f32);
+        let index = position as usize;
+        let fraction = position - index as f32;
+        let next = (index + 1) % line.len();
+        line[index] * (1.0 - fraction) + line[next] * fraction
+    }
+}
+
+impl<S: Source> Iterator for Chorus<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let dry = self.tail.next(&mut self.input)?;
+
+        // Offset each channel's sweep so stereo sources widen
+        let phase = self.lfo_phase + self.channel as f32 / self.lines.len() as f32;
+        let sweep = (TAU * phase).sin();
+        let delay = (self.settings.delay.as_secs_f32() + self.settings.depth.as_secs_f32() * sweep)
+            * self.sample_rate as f32;
+
+        self.lines[self.channel][self.write] = dry;
+        let wet = self.read(delay);
+
+        self.channel += 1;
+        if self.channel == self.lines.len() {
+            self.channel = 0;
+            self.write = (self.write + 1) % self.lines[0].len();
+            self.lfo_phase =
+                (self.lfo_phase + self.settings.rate / self.sample_rate as f32).rem_euclid(1.0);
+        }
+
+        Some(dry * (1.0 - self.settings.mix) + wet * self.settings.mix)
+    }
+}
+
+impl<S: Source> Source for Chorus<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input
+            .total_duration()
+            .map(|duration| duration + self.settings.tail())
+    }
+}
@@ -0,0 +1,118 @@
+//! Feedback delay
+
+use super::Tail;
+use rodio::Source;
+use std::time::Duration;
+
+/// Delay time, feedback and wet level for a [`Delay`]
+#[derive(Debug, Clone, Copy)]
+pub struct DelaySettings {
+    pub time: Duration,
+    pub feedback: f32,
+    pub mix: f32,
+}
+
+impl Default for DelaySettings {
+    fn default() -> Self {
+        Self {
+            time: Duration::from_millis(375),
+            feedback: 0.35,
+            mix: 0.3,
+        }
+    }
+}
+
+impl DelaySettings {
+    /// Delay time locked to the tempo, measured in beats (quarter notes)
+    pub fn synced(bpm: u32, beats: f32) -> Self {
+        Self::default().with_time(Duration::from_secs_f32(60.0 / bpm as f32 * beats))
+    }
+
+    pub fn with_time(mut self, time: Duration) -> Self {
+        self.time = time;
+        self
+    }
+
+    pub fn with_feedback(mut self, feedback: f32) -> Self {
+        self.feedback = feedback.clamp(0.0, 0.95);
+        self
+    }
+
+    pub fn with_mix(mut self, mix: f32) -> Self {
+        self.mix = mix.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Time for the repeats to fall below -60 dB
+    pub fn tail(&self) -> Duration {
+        let repeats = if self.feedback > 0.0 {
+            (0.001f32.ln() / self.feedback.ln()).ceil()
+        } else {
+            1.0
+        };
+        self.time.mul_f32(repeats)
+    }
+}
+
+/// Feedback delay line wrapped around a source
+pub struct Delay<S> {
+    input: S,
+    settings: DelaySettings,
+    channels: u16,
+    sample_rate: u32,
+    buffer: Vec<f32>,
+    position: usize,
+    tail: Tail,
+}
+
+impl<S: Source> Delay<S> {
+    pub fn new(input: S, settings: DelaySettings) -> Self {
+        let channels = input.channels();
+        let sample_rate = input.sample_rate();
+        let frames = (settings.time.as_secs_f32() * sample_rate as f32).max(1.0) as usize;
+        Self {
+            input,
+            settings,
+            channels,
+            sample_rate,
+            // Interleaved, so each channel only ever reads back its own samples
+            buffer: vec![0.0; frames * channels as usize],
+            position: 0,
+            tail: Tail::new(settings.tail(), sample_rate, channels),
+        }
+    }
+}
+
+impl<S: Source> Iterator for Delay<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let dry = self.tail.next(&mut self.input)?;
+
+        let delayed = self.buffer[self.position];
+        self.buffer[self.position] = dry + delayed * self.settings.feedback;
+        self.position = (self.position + 1) % self.buffer.len();
+
+        Some(dry + delayed * self.settings.mix)
+    }
+}
+
+impl<S: Source> Source for Delay<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input
+            .total_duration()
+            .map(|duration| duration + self.settings.tail())
+    }
+}
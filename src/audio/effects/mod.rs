@@ -0,0 +1,97 @@
+//! Effects that can be inserted after a mixer: delay, reverb and chorus
+
+pub mod chorus;
+pub mod delay;
+pub mod reverb;
+
+pub use chorus::{Chorus, ChorusSettings};
+pub use delay::{Delay, DelaySettings};
+pub use reverb::{Reverb, ReverbSettings};
+
+use rodio::Source;
+use std::time::Duration;
+
+/// An ordered set of effects: chorus, then delay, then reverb
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EffectsChain {
+    pub chorus: Option<ChorusSettings>,
+    pub delay: Option<DelaySettings>,
+    pub reverb: Option<ReverbSettings>,
+}
+
+impl EffectsChain {
+    pub fn is_empty(&self) -> bool {
+        self.chorus.is_none() && self.delay.is_none() && self.reverb.is_none()
+    }
+
+    /// Fill any effect this chain leaves out from `other`
+    pub fn or(self, other: EffectsChain) -> EffectsChain {
+        EffectsChain {
+            chorus: self.chorus.or(other.chorus),
+            delay: self.delay.or(other.delay),
+            reverb: self.reverb.or(other.reverb),
+        }
+    }
+
+    /// How long the chain keeps sounding after its input has stopped
+    pub fn tail(&self) -> Duration {
+        self.chorus.map(|c| c.tail()).unwrap_or_default()
+            + self.delay.map(|d| d.tail()).unwrap_or_default()
+            + self.reverb.map(|r| r.tail()).unwrap_or_default()
+    }
+
+    /// Wrap a source in every enabled effect
+    pub fn apply<S>(&self, source: S) -> Box<dyn Source + Send>
+    where
+        S: Source + Send + 'static,
+    {
+        let mut source: Box<dyn Source + Send> = Box::new(source);
+        if let Some(settings) = self.chorus {
+            source = Box::new(Chorus::new(source, settings));
+        }
+        if let Some(settings) = self.delay {
+            source = Box::new(Delay::new(source, settings));
+        }
+        if let Some(settings) = self.reverb {
+            source = Box::new(Reverb::new(source, settings));
+        }
+        source
+    }
+}
+
+/// Keeps an effect running for its tail once the input runs dry.
+///
+/// The input is still polled while the tail plays, so a live mixer that
+/// receives new sources brings the effect back to normal operation.
+struct Tail {
+    length: usize,
+    remaining: Option<usize>,
+}
+
+impl Tail {
+    fn new(duration: Duration, sample_rate: u32, channels: u16) -> Self {
+        let length = (duration.as_secs_f32() * sample_rate as f32) as usize * channels as usize;
+        Self {
+            length,
+            remaining: None,
+        }
+    }
+
+    /// Returns the dry input sample, silence while the tail rings out, or `None` once it is done
+    fn next<S: Source>(&mut self, input: &mut S) -> Option<f32> {
+        match input.next() {
+            Some(sample) => {
+                self.remaining = None;
+                Some(sample)
+            }
+            None => {
+                let remaining = self.remaining.get_or_insert(self.length);
+                if *remaining == 0 {
+                    return None;
+                }
+                *remaining -= 1;
+                Some(0.0)
+            }
+        }
+    }
+}
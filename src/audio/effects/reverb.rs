@@ -0,0 +1,205 @@
+//! Freeverb-style reverb: parallel damped combs into series allpasses
+
+use super::Tail;
+use rodio::Source;
+use std::time::Duration;
+
+// Jezar's tunings, in samples at 44.1 kHz
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+
+const FIXED_GAIN: f32 = 0.015;
+const SCALE_ROOM: f32 = 0.28;
+const OFFSET_ROOM: f32 = 0.7;
+const SCALE_DAMP: f32 = 0.4;
+const SCALE_WET: f32 = 3.0;
+
+/// Room size, damping and wet level for a [`Reverb`]
+#[derive(Debug, Clone, Copy)]
+pub struct ReverbSettings {
+    pub room_size: f32,
+    pub damping: f32,
+    pub mix: f32,
+}
+
+impl Default for ReverbSettings {
+    fn default() -> Self {
+        Self {
+            room_size: 0.6,
+            damping: 0.5,
+            mix: 0.25,
+        }
+    }
+}
+
+impl ReverbSettings {
+    pub fn with_room_size(mut self, room_size: f32) -> Self {
+        self.room_size = room_size.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_damping(mut self, damping: f32) -> Self {
+        self.damping = damping.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_mix(mut self, mix: f32) -> Self {
+        self.mix = mix.clamp(0.0, 1.0);
+        self
+    }
+
+    fn feedback(&self) -> f32 {
+        self.room_size * SCALE_ROOM + OFFSET_ROOM
+    }
+
+    /// Time for the longest comb to decay by 60 dB
+    pub fn tail(&self) -> Duration {
+        let longest = (COMB_TUNINGS[7] + STEREO_SPREAD) as f32 / 44_100.0;
+        let repeats = 0.001f32.ln() / self.feedback().ln();
+        Duration::from_secs_f32(longest * repeats)
+    }
+}
+
+struct Comb {
+    buffer: Vec<f32>,
+    index: usize,
+    filter_store: f32,
+}
+
+impl Comb {
+    fn new(size: usize) -> Self {
+        Self {
+            buffer: vec![0.0; size.max(1)],
+            index: 0,
+            filter_store: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.index];
+        self.filter_store = output * (1.0 - damping) + self.filter_store * damping;
+        self.buffer[self.index] = input + self.filter_store * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+        output
+    }
+}
+
+struct Allpass {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl Allpass {
+    fn new(size: usize) -> Self {
+        Self {
+            buffer: vec![0.0; size.max(1)],
+            index: 0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let buffered = self.buffer[self.index];
+        self.buffer[self.index] = input + buffered * 0.5;
+        self.index = (self.index + 1) % self.buffer.len();
+        buffered - input
+    }
+}
+
+/// One channel's worth of filters; each channel is offset by the stereo spread
+struct Tank {
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+}
+
+impl Tank {
+    fn new(sample_rate: u32, spread: usize) -> Self {
+        let scale = |tuning: usize| (tuning + spread) * sample_rate as usize / 44_100;
+        Self {
+            combs: COMB_TUNINGS.iter().map(|&t| Comb::new(scale(t))).collect(),
+            allpasses: ALLPASS_TUNINGS
+                .iter()
+                .map(|&t| Allpass::new(scale(t)))
+                .collect(),
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let input = input * FIXED_GAIN;
+        let mut output: f32 = self
+            .combs
+            .iter_mut()
+            .map(|comb| comb.process(input, feedback, damping))
+            .sum();
+        for allpass in &mut self.allpasses {
+            output = allpass.process(output);
+        }
+        output
+    }
+}
+
+/// Reverb wrapped around a source
+pub struct Reverb<S> {
+    input: S,
+    settings: ReverbSettings,
+    channels: u16,
+    sample_rate: u32,
+    tanks: Vec<Tank>,
+    channel: usize,
+    tail: Tail,
+}
+
+impl<S: Source> Reverb<S> {
+    pub fn new(input: S, settings: ReverbSettings) -> Self {
+        let channels = input.channels();
+        let sample_rate = input.sample_rate();
+        Self {
+            input,
+            settings,
+            channels,
+            sample_rate,
+            tanks: (0..channels as usize)
+                .map(|c| Tank::new(sample_rate, c * STEREO_SPREAD))
+                .collect(),
+            channel: 0,
+            tail: Tail::new(settings.tail(), sample_rate, channels),
+        }
+    }
+}
+
+impl<S: Source> Iterator for Reverb<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let dry = self.tail.next(&mut self.input)?;
+
+        let wet = self.tanks[self.channel].process(
+            dry,
+            self.settings.feedback(),
+            self.settings.damping * SCALE_DAMP,
+        );
+        self.channel = (self.channel + 1) % self.tanks.len();
+
+        Some(dry * (1.0 - self.settings.mix) + wet * self.settings.mix * SCALE_WET)
+    }
+}
+
+impl<S: Source> Source for Reverb<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input
+            .total_duration()
+            .map(|duration| duration + self.settings.tail())
+    }
+}
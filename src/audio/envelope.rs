@@ -0,0 +1,114 @@
+//! ADSR amplitude envelope
+
+use rodio::Source;
+use std::time::Duration;
+
+/// Attack, decay, sustain and release for an [`Envelope`]
+#[derive(Debug, Clone, Copy)]
+pub struct EnvelopeSettings {
+    pub attack: Duration,
+    pub decay: Duration,
+    /// Level held after the decay, from 0.0 to 1.0
+    pub sustain: f32,
+    pub release: Duration,
+}
+
+impl Default for EnvelopeSettings {
+    fn default() -> Self {
+        Self {
+            attack: Duration::from_millis(5),
+            decay: Duration::ZERO,
+            sustain: 1.0,
+            release: Duration::from_millis(5),
+        }
+    }
+}
+
+/// Shapes the loudness of one note.
+///
+/// The release is fitted inside the note, ending with its last sample. Sources
+/// without a known length hold the sustain level forever.
+pub struct Envelope<S> {
+    input: S,
+    settings: EnvelopeSettings,
+    sample_rate: f32,
+    channels: u16,
+    frame: usize,
+    channel: u16,
+    release_start: Option<f32>,
+}
+
+impl<S: Source> Envelope<S> {
+    pub fn new(input: S, settings: EnvelopeSettings) -> Self {
+        let release_start = input
+            .total_duration()
+            .map(|total| total.saturating_sub(settings.release).as_secs_f32());
+        Self {
+            sample_rate: input.sample_rate() as f32,
+            channels: input.channels().max(1),
+            input,
+            settings,
+            frame: 0,
+            channel: 0,
+            release_start,
+        }
+    }
+
+    /// Level before any release, at `time` seconds into the note
+    fn held_level(&self, time: f32) -> f32 {
+        let attack = self.settings.attack.as_secs_f32();
+        if time < attack {
+            return time / attack;
+        }
+        let decay = self.settings.decay.as_secs_f32();
+        if time < attack + decay {
+            let progress = (time - attack) / decay;
+            return 1.0 - (1.0 - self.settings.sustain) * progress;
+        }
+        self.settings.sustain
+    }
+
+    fn level(&self) -> f32 {
+        let time = self.frame as f32 / self.sample_rate;
+        match self.release_start {
+            Some(start) if time >= start => {
+                let release = self.settings.release.as_secs_f32().max(f32::EPSILON);
+                let progress = ((time - start) / release).min(1.0);
+                self.held_level(start) * (1.0 - progress)
+            }
+            _ => self.held_level(time),
+        }
+    }
+}
+
+impl<S: Source> Iterator for Envelope<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.input.next()? * self.level();
+        self.channel += 1;
+        if self.channel == self.channels {
+            self.channel = 0;
+            self.frame += 1;
+        }
+        Some(sample)
+    }
+}
+
+impl<S: Source> Source for Envelope<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        self.input.current_span_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
@@ -0,0 +1,86 @@
+//! Resonant low-pass filter
+
+use rodio::Source;
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+/// Cutoff and resonance for a [`LowPass`]
+#[derive(Debug, Clone, Copy)]
+pub struct FilterSettings {
+    /// Cutoff frequency in Hz
+    pub cutoff: f32,
+    /// Filter Q; 0.707 is flat, higher values ring at the cutoff
+    pub resonance: f32,
+}
+
+impl Default for FilterSettings {
+    fn default() -> Self {
+        Self {
+            cutoff: 2000.0,
+            resonance: 0.707,
+        }
+    }
+}
+
+/// Biquad low-pass (RBJ cookbook) with separate state per channel
+pub struct LowPass<S> {
+    input: S,
+    coefficients: [f32; 5],
+    /// x1, x2, y1, y2 for each channel
+    state: Vec<[f32; 4]>,
+    channel: usize,
+}
+
+impl<S: Source> LowPass<S> {
+    pub fn new(input: S, settings: FilterSettings) -> Self {
+        let sample_rate = input.sample_rate() as f32;
+        let cutoff = settings.cutoff.clamp(10.0, sample_rate * 0.49);
+        let omega = TAU * cutoff / sample_rate;
+        let alpha = omega.sin() / (2.0 * settings.resonance.max(0.1));
+        let cos = omega.cos();
+
+        let a0 = 1.0 + alpha;
+        let b1 = (1.0 - cos) / a0;
+        let coefficients = [b1 / 2.0, b1, b1 / 2.0, -2.0 * cos / a0, (1.0 - alpha) / a0];
+
+        Self {
+            state: vec![[0.0; 4]; input.channels().max(1) as usize],
+            input,
+            coefficients,
+            channel: 0,
+        }
+    }
+}
+
+impl<S: Source> Iterator for LowPass<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let x = self.input.next()?;
+        let [b0, b1, b2, a1, a2] = self.coefficients;
+        let [x1, x2, y1, y2] = self.state[self.channel];
+
+        let y = b0 * x + b1 * x1 + b2 * x2 - a1 * y1 - a2 * y2;
+        self.state[self.channel] = [x, x1, y, y1];
+        self.channel = (self.channel + 1) % self.state.len();
+        Some(y)
+    }
+}
+
+impl<S: Source> Source for LowPass<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        self.input.current_span_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
@@ -0,0 +1,213 @@
+//! Frequency modulation oscillator with two to four operators
+
+use super::oscillator::Oscillator;
+use crate::error::SoundError;
+use std::f32::consts::TAU;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// One sine operator of an FM stack
+#[derive(Debug, Clone, Copy)]
+pub struct Operator {
+    /// Frequency as a multiple of the note frequency
+    pub ratio: f32,
+    /// Peak modulation index applied to the operator below (unused for the carrier)
+    pub index: f32,
+}
+
+/// Shape of the modulation index over the life of a note
+#[derive(Debug, Clone, Copy)]
+pub struct IndexEnvelope {
+    pub attack: Duration,
+    pub decay: Duration,
+    /// Fraction of the peak index held once the decay has settled
+    pub sustain: f32,
+}
+
+impl IndexEnvelope {
+    fn level(&self, time: f32) -> f32 {
+        let attack = self.attack.as_secs_f32();
+        if time < attack {
+            return time / attack;
+        }
+        let decay = self.decay.as_secs_f32().max(f32::EPSILON);
+        self.sustain + (1.0 - self.sustain) * (-(time - attack) / decay).exp()
+    }
+}
+
+/// A serial FM stack: each operator modulates the one before it, ending at
+/// the carrier (`operators[0]`)
+#[derive(Debug, Clone)]
+pub struct FmSettings {
+    pub operators: Vec<Operator>,
+    pub envelope: IndexEnvelope,
+}
+
+impl FmSettings {
+    pub const MIN_OPERATORS: usize = 2;
+    pub const MAX_OPERATORS: usize = 4;
+
+    /// Inharmonic ratio with a long index decay
+    pub fn bell() -> Self {
+        Self {
+            operators: vec![
+                Operator {
+                    ratio: 1.0,
+                    index: 0.0,
+                },
+                Operator {
+                    ratio: 3.5,
+                    index: 5.0,
+                },
+            ],
+            envelope: IndexEnvelope {
+                attack: Duration::ZERO,
+                decay: Duration::from_millis(1500),
+                sustain: 0.05,
+            },
+        }
+    }
+
+    /// Harmonic body with a bright, quickly fading tine
+    pub fn electric_piano() -> Self {
+        Self {
+            operators: vec![
+                Operator {
+                    ratio: 1.0,
+                    index: 0.0,
+                },
+                Operator {
+                    ratio: 1.0,
+                    index: 1.8,
+                },
+                Operator {
+                    ratio: 14.0,
+                    index: 0.6,
+                },
+            ],
+            envelope: IndexEnvelope {
+                attack: Duration::ZERO,
+                decay: Duration::from_millis(400),
+                sustain: 0.25,
+            },
+        }
+    }
+
+    /// Parse `bell`, `epiano`, or a stack such as `1,3.5*5,decay=1.5`.
+    ///
+    /// Operators are `ratio` or `ratio*index`, carrier first; `attack`,
+    /// `decay` (seconds) and `sustain` shape the modulation index.
+    pub fn parse(spec: &str) -> Result<Self, SoundError> {
+        match spec.to_lowercase().as_str() {
+            "bell" => return Ok(Self::bell()),
+            "epiano" | "electric_piano" => return Ok(Self::electric_piano()),
+            _ => {}
+        }
+
+        let mut settings = Self {
+            operators: Vec::new(),
+            envelope: IndexEnvelope {
+                attack: Duration::ZERO,
+                decay: Duration::from_secs(1),
+                sustain: 0.5,
+            },
+        };
+
+        let number = |value: &str| {
+            value
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|v| v.is_finite() && *v >= 0.0)
+                .ok_or_else(|| SoundError::Invalid(format!("Invalid FM value '{}'", value)))
+        };
+
+        for item in spec.split(',') {
+            if let Some((name, value)) = item.split_once('=') {
+                let value = number(value)?;
+                match name.trim() {
+                    "attack" => settings.envelope.attack = Duration::from_secs_f32(value),
+                    "decay" => settings.envelope.decay = Duration::from_secs_f32(value),
+                    "sustain" => settings.envelope.sustain = value.min(1.0),
+                    other => {
+                        return Err(SoundError::Invalid(format!(
+                            "Unknown FM setting '{}'",
+                            other
+                        )))
+                    }
+                }
+                continue;
+            }
+            let (ratio, index) = match item.split_once('*') {
+                Some((ratio, index)) => (number(ratio)?, number(index)?),
+                None => (number(item)?, 1.0),
+            };
+            settings.operators.push(Operator { ratio, index });
+        }
+
+        if !(Self::MIN_OPERATORS..=Self::MAX_OPERATORS).contains(&settings.operators.len()) {
+            return Err(SoundError::Invalid(format!(
+                "FM needs {} to {} operators, got {}",
+                Self::MIN_OPERATORS,
+                Self::MAX_OPERATORS,
+                settings.operators.len()
+            )));
+        }
+        Ok(settings)
+    }
+}
+
+/// Plays an [`FmSettings`] stack.
+///
+/// Operators keep their own phases because their ratios need not be whole
+/// numbers, so the phase passed in by the note source is not used.
+#[derive(Debug, Clone)]
+pub struct Fm {
+    settings: Arc<FmSettings>,
+    phases: Vec<f32>,
+    steps: Vec<f32>,
+    time: f32,
+    time_step: f32,
+}
+
+impl Fm {
+    pub fn new(settings: Arc<FmSettings>) -> Self {
+        let operators = settings.operators.len();
+        Self {
+            settings,
+            phases: vec![0.0; operators],
+            steps: vec![0.0; operators],
+            time: 0.0,
+            time_step: 0.0,
+        }
+    }
+}
+
+impl Oscillator for Fm {
+    fn start(&mut self, frequency: f32, sample_rate: u32) {
+        let step = frequency / sample_rate as f32;
+        for (operator, operator_step) in self.settings.operators.iter().zip(&mut self.steps) {
+            *operator_step = step * operator.ratio;
+        }
+        self.time_step = 1.0 / sample_rate as f32;
+    }
+
+    fn sample(&mut self, _phase: f32) -> f32 {
+        let envelope = self.settings.envelope.level(self.time);
+        self.time += self.time_step;
+
+        // Work down the stack from the top modulator to the carrier
+        let mut modulation = 0.0;
+        let mut output = 0.0;
+        for (i, operator) in self.settings.operators.iter().enumerate().rev() {
+            output = (TAU * self.phases[i] + modulation).sin();
+            modulation = output * operator.index * envelope;
+        }
+
+        for (phase, step) in self.phases.iter_mut().zip(&self.steps) {
+            *phase = (*phase + step).rem_euclid(1.0);
+        }
+
+        output
+    }
+}
@@ -0,0 +1,353 @@
+//! Instrument presets: oscillator, envelope, filter, effects and gain
+//!
+//! Presets are TOML files:
+//!
+//! ```toml
+//! wave = "sawtooth"        # anything --wave accepts
+//! gain = 0.8
+//!
+//! [envelope]               # seconds, sustain level 0.0-1.0
+//! attack = 0.01
+//! decay = 0.2
+//! sustain = 0.6
+//! release = 0.1
+//!
+//! [filter]
+//! cutoff = 1800.0          # Hz
+//! resonance = 1.2
+//!
+//! [effects.delay]
+//! beats = 0.75             # or time = 0.3 (seconds)
+//! feedback = 0.4
+//! mix = 0.3
+//!
+//! [effects.reverb]
+//! room_size = 0.7
+//! damping = 0.5
+//! mix = 0.25
+//!
+//! [effects.chorus]
+//! rate = 0.8               # Hz
+//! depth = 0.003            # seconds
+//! delay = 0.015            # seconds
+//! mix = 0.5
+//! ```
+
+use super::{
+    effects::{ChorusSettings, DelaySettings, EffectsChain, ReverbSettings},
+    envelope::{Envelope, EnvelopeSettings},
+    filter::{FilterSettings, LowPass},
+    oscillator::NoteSource,
+    wave::{get_wave_type, WaveType},
+};
+use crate::error::SoundError;
+use crate::MusicNote;
+use rodio::Source;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Everything that shapes how a voice sounds
+#[derive(Debug, Clone)]
+pub struct Instrument {
+    pub wave: WaveType,
+    pub envelope: Option<EnvelopeSettings>,
+    pub filter: Option<FilterSettings>,
+    pub effects: EffectsChain,
+    pub gain: f32,
+}
+
+impl Instrument {
+    /// A bare oscillator with no envelope, filter or effects
+    pub fn from_wave(wave: WaveType) -> Self {
+        Self {
+            wave,
+            envelope: None,
+            filter: None,
+            effects: EffectsChain::default(),
+            gain: 1.0,
+        }
+    }
+
+    /// Load a preset file; `bpm` sets the length of tempo-synced delays
+    pub fn load<P: AsRef<Path>>(path: P, bpm: u32) -> Result<Self, SoundError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|error| SoundError::Io {
+            path: path.display().to_string(),
+            message: format!("cannot read: {}", error),
+        })?;
+        Self::from_toml(&text, bpm).map_err(|error| SoundError::Io {
+            path: path.display().to_string(),
+            message: error.to_string(),
+        })
+    }
+
+    pub fn from_toml(text: &str, bpm: u32) -> Result<Self, SoundError> {
+        let preset: Preset =
+            toml::from_str(text).map_err(|error| SoundError::Invalid(error.to_string()))?;
+
+        let wave = match preset.wave {
+            Some(wave) => get_wave_type(&wave)?,
+            None => WaveType::Sine,
+        };
+        let mut instrument = Self::from_wave(wave);
+        instrument.gain = preset.gain.unwrap_or(1.0);
+
+        if let Some(envelope) = preset.envelope {
+            let defaults = EnvelopeSettings::default();
+            instrument.envelope = Some(EnvelopeSettings {
+                attack: seconds("envelope.attack", envelope.attack)?.unwrap_or(defaults.attack),
+                decay: seconds("envelope.decay", envelope.decay)?.unwrap_or(defaults.decay),
+                sustain: envelope.sustain.unwrap_or(defaults.sustain).clamp(0.0, 1.0),
+                release: seconds("envelope.release", envelope.release)?.unwrap_or(defaults.release),
+            });
+        }
+
+        if let Some(filter) = preset.filter {
+            let defaults = FilterSettings::default();
+            instrument.filter = Some(FilterSettings {
+                cutoff: filter.cutoff.unwrap_or(defaults.cutoff),
+                resonance: filter.resonance.unwrap_or(defaults.resonance),
+            });
+        }
+
+        if let Some(effects) = preset.effects {
+            instrument.effects = effects_chain(effects, bpm)?;
+        }
+
+        Ok(instrument)
+    }
+
+    /// One note of this instrument lasting `length` samples, shaped by its envelope
+    pub fn note(
+        &self,
+        note: &MusicNote,
+        sample_rate: u32,
+        length: usize,
+    ) -> Box<dyn Source + Send> {
+        self.envelop(NoteSource::from_note(
+            self.wave.oscillator(),
+            note,
+            sample_rate,
+            length,
+        ))
+    }
+
+    /// A note that sounds until it is stopped
+    pub fn held_note(&self, frequency: f32, sample_rate: u32) -> Box<dyn Source + Send> {
+        self.envelop(NoteSource::infinite(
+            self.wave.oscillator(),
+            frequency,
+            sample_rate,
+        ))
+    }
+
+    fn envelop<S>(&self, source: S) -> Box<dyn Source + Send>
+    where
+        S: Source + Send + 'static,
+    {
+        match self.envelope {
+            Some(settings) => Box::new(Envelope::new(source, settings)),
+            None => Box::new(source),
+        }
+    }
+
+    /// Apply the filter and gain to a whole voice (effects are left to the caller)
+    pub fn voice<S>(&self, source: S) -> Box<dyn Source + Send>
+    where
+        S: Source + Send + 'static,
+    {
+        match self.filter {
+            Some(settings) => Box::new(LowPass::new(source, settings).amplify(self.gain)),
+            None => Box::new(source.amplify(self.gain)),
+        }
+    }
+}
+
+/// A preset file as written, before defaults are filled in
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Preset {
+    wave: Option<String>,
+    gain: Option<f32>,
+    envelope: Option<EnvelopePreset>,
+    filter: Option<FilterPreset>,
+    effects: Option<EffectsPreset>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvelopePreset {
+    attack: Option<f32>,
+    decay: Option<f32>,
+    sustain: Option<f32>,
+    release: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FilterPreset {
+    cutoff: Option<f32>,
+    resonance: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EffectsPreset {
+    delay: Option<DelayPreset>,
+    reverb: Option<ReverbPreset>,
+    chorus: Option<ChorusPreset>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DelayPreset {
+    beats: Option<f32>,
+    time: Option<f32>,
+    feedback: Option<f32>,
+    mix: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReverbPreset {
+    room_size: Option<f32>,
+    damping: Option<f32>,
+    mix: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChorusPreset {
+    rate: Option<f32>,
+    depth: Option<f32>,
+    delay: Option<f32>,
+    mix: Option<f32>,
+}
+
+/// A length in seconds, which must be finite and cannot be negative
+fn seconds(name: &str, value: Option<f32>) -> Result<Option<Duration>, String> {
+    match value {
+        Some(seconds) if !seconds.is_finite() => Err(format!("{} must be a finite number", name)),
+        Some(seconds) if seconds < 0.0 => Err(format!("{} cannot be negative", name)),
+        Some(seconds) => Duration::try_from_secs_f32(seconds)
+            .map(Some)
+            .map_err(|_| format!("{} is too long", name)),
+        None => Ok(None),
+    }
+}
+
+fn effects_chain(effects: EffectsPreset, bpm: u32) -> Result<EffectsChain, String> {
+    let mut chain = EffectsChain::default();
+
+    if let Some(section) = effects.delay {
+        let defaults = DelaySettings::synced(bpm, 0.75);
+        let time = seconds("effects.delay.time", section.time)?;
+        // Beats are checked as the length of time they last at this tempo
+        let beats = section.beats.map(|beats| beats * 60.0 / bpm as f32);
+        let beats = seconds("effects.delay.beats", beats)?;
+        let mut delay = match (beats, time) {
+            (Some(_), Some(_)) => return Err("Delay takes either beats or time, not both".into()),
+            (Some(time), None) | (None, Some(time)) => defaults.with_time(time),
+            (None, None) => defaults,
+        };
+        if let Some(feedback) = section.feedback {
+            delay = delay.with_feedback(feedback);
+        }
+        if let Some(mix) = section.mix {
+            delay = delay.with_mix(mix);
+        }
+        chain.delay = Some(delay);
+    }
+
+    if let Some(section) = effects.reverb {
+        let mut reverb = ReverbSettings::default();
+        if let Some(room_size) = section.room_size {
+            reverb = reverb.with_room_size(room_size);
+        }
+        if let Some(damping) = section.damping {
+            reverb = reverb.with_damping(damping);
+        }
+        if let Some(mix) = section.mix {
+            reverb = reverb.with_mix(mix);
+        }
+        chain.reverb = Some(reverb);
+    }
+
+    if let Some(section) = effects.chorus {
+        let mut chorus = ChorusSettings::default();
+        if let Some(delay) = seconds("effects.chorus.delay", section.delay)? {
+            chorus = chorus.with_delay(delay);
+        }
+        if let Some(depth) = seconds("effects.chorus.depth", section.depth)? {
+            chorus = chorus.with_depth(depth);
+        }
+        if let Some(rate) = section.rate {
+            chorus = chorus.with_rate(rate);
+        }
+        if let Some(mix) = section.mix {
+            chorus = chorus.with_mix(mix);
+        }
+        chain.chorus = Some(chorus);
+    }
+
+    Ok(chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_a_full_preset() {
+        let preset = r#"
+            wave = "square"
+            gain = 1    # integers are read as numbers
+
+            [envelope]
+            attack = 0.5
+            sustain = 2.0
+
+            [filter]
+            cutoff = 1200.0
+
+            [effects.reverb]
+            mix = 0.25
+        "#;
+        let instrument = Instrument::from_toml(preset, 120).unwrap();
+        assert_eq!(instrument.gain, 1.0);
+        let envelope = instrument.envelope.unwrap();
+        assert_eq!(envelope.attack, Duration::from_millis(500));
+        assert_eq!(envelope.sustain, 1.0);
+        assert_eq!(instrument.filter.unwrap().cutoff, 1200.0);
+        assert!(instrument.effects.reverb.is_some());
+        assert!(instrument.effects.delay.is_none());
+    }
+
+    #[test]
+    fn defaults_to_a_bare_sine() {
+        let instrument = Instrument::from_toml("", 120).unwrap();
+        assert!(matches!(instrument.wave, WaveType::Sine));
+        assert!(instrument.envelope.is_none());
+        assert_eq!(instrument.gain, 1.0);
+    }
+
+    #[test]
+    fn rejects_bad_presets() {
+        for preset in [
+            "volume = 0.5",
+            "gain = \"loud\"",
+            "[envelope]\nattack = -1.0",
+            "[effects.delay]\nbeats = 1.0\ntime = 0.5",
+            "[effects.flanger]",
+            "[envelope]\nattack = nan",
+            "[envelope]\nattack = 1e30",
+            "[effects.chorus]\ndepth = inf",
+            "[effects.delay]\nbeats = -1.0",
+            "[effects.delay]\nbeats = 1e30",
+        ] {
+            assert!(Instrument::from_toml(preset, 120).is_err(), "{}", preset);
+        }
+    }
+}
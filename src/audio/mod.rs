@@ -1,4 +1,21 @@
+pub mod additive;
+pub mod bus;
+pub mod effects;
+pub mod envelope;
+pub mod filter;
+pub mod fm;
+pub mod instrument;
+pub mod oscillator;
 pub mod pulse;
+pub mod render;
+pub mod sampler;
+pub mod sawtooth;
+pub mod sine;
 pub mod square;
+pub mod stereo;
 pub mod triangle;
 pub mod wave;
+pub mod wavetable;
+
+/// Sample rate used for every generated source
+pub const SAMPLE_RATE: u32 = 44100;
@@ -0,0 +1,111 @@
+//! Oscillator trait and the note source that plays any oscillator
+
+use crate::music::note::MusicNote;
+use rodio::Source;
+use std::time::Duration;
+
+/// A waveform that can be played at any pitch.
+///
+/// [`NoteSource`] owns the phase, so a simple waveform only has to map a
+/// position in the cycle to a sample value.
+pub trait Oscillator: Send {
+    /// Called once before the first sample of a note
+    fn start(&mut self, _frequency: f32, _sample_rate: u32) {}
+
+    /// Waveform value at `phase`, the position through one cycle (0.0 to 1.0)
+    fn sample(&mut self, phase: f32) -> f32;
+}
+
+impl Oscillator for Box<dyn Oscillator> {
+    fn start(&mut self, frequency: f32, sample_rate: u32) {
+        (**self).start(frequency, sample_rate)
+    }
+
+    fn sample(&mut self, phase: f32) -> f32 {
+        (**self).sample(phase)
+    }
+}
+
+/// Plays an oscillator at a fixed frequency, for a set duration or forever
+pub struct NoteSource<O> {
+    oscillator: O,
+    frequency: f32,
+    sample_rate: u32,
+    phase: f32,
+    phase_step: f32,
+    samples_played: usize,
+    limit: Option<usize>,
+}
+
+impl<O: Oscillator> NoteSource<O> {
+    /// A note lasting exactly `length` samples
+    pub fn finite(oscillator: O, frequency: f32, sample_rate: u32, length: usize) -> Self {
+        Self::new(oscillator, frequency, sample_rate, Some(length))
+    }
+
+    pub fn infinite(oscillator: O, frequency: f32, sample_rate: u32) -> Self {
+        Self::new(oscillator, frequency, sample_rate, None)
+    }
+
+    pub fn from_note(oscillator: O, note: &MusicNote, sample_rate: u32, length: usize) -> Self {
+        Self::finite(oscillator, note.frequency(), sample_rate, length)
+    }
+
+    fn new(mut oscillator: O, frequency: f32, sample_rate: u32, limit: Option<usize>) -> Self {
+        if frequency > 0.0 {
+            oscillator.start(frequency, sample_rate);
+        }
+        Self {
+            oscillator,
+            frequency,
+            sample_rate,
+            phase: 0.0,
+            phase_step: frequency / sample_rate as f32,
+            samples_played: 0,
+            limit,
+        }
+    }
+}
+
+impl<O: Oscillator> Iterator for NoteSource<O> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(limit) = self.limit {
+            if self.samples_played >= limit {
+                return None;
+            }
+        }
+
+        let sample = if self.frequency == 0.0 {
+            // Rest note - silence
+            0.0
+        } else {
+            self.oscillator.sample(self.phase)
+        };
+
+        self.phase = (self.phase + self.phase_step).rem_euclid(1.0f32);
+
+        self.samples_played += 1;
+        Some(sample)
+    }
+}
+
+impl<O: Oscillator> Source for NoteSource<O> {
+    fn current_span_len(&self) -> Option<usize> {
+        self.limit.map(|lim| lim - self.samples_played)
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.limit
+            .map(|lim| Duration::from_secs_f32(lim as f32 / self.sample_rate as f32))
+    }
+}
@@ -0,0 +1,43 @@
+//! Offline rendering of sources to WAV files
+
+use rodio::Source;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Render a finite source to a 16-bit PCM WAV file
+pub fn write_wav<S, P>(path: P, source: S) -> io::Result<()>
+where
+    S: Source,
+    P: AsRef<Path>,
+{
+    let channels = source.channels();
+    let sample_rate = source.sample_rate();
+    let samples: Vec<i16> = source
+        .map(|sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+        .collect();
+
+    let data_len = (samples.len() * 2) as u32;
+    let block_align = channels * 2;
+
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?; // PCM
+    out.write_all(&channels.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    out.write_all(&block_align.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    for sample in samples {
+        out.write_all(&sample.to_le_bytes())?;
+    }
+    out.flush()
+}
@@ -0,0 +1,220 @@
+//! Sample-based instrument: recorded audio repitched to each note
+
+use super::{oscillator::Oscillator, wavetable::load_mono};
+use crate::error::SoundError;
+use crate::music::util::parse_note_with_octave;
+use crate::Note;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Decoded mono audio
+#[derive(Debug)]
+pub struct SampleData {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+}
+
+impl SampleData {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SoundError> {
+        let path = path.as_ref();
+        let (samples, sample_rate) = load_mono(path)?;
+        if samples.is_empty() {
+            return Err(SoundError::Io {
+                path: path.display().to_string(),
+                message: "sample contains no audio".to_string(),
+            });
+        }
+        Ok(Self {
+            samples,
+            sample_rate,
+        })
+    }
+}
+
+/// One sample mapped onto a range of keys
+#[derive(Debug, Clone)]
+pub struct SampleZone {
+    pub data: Arc<SampleData>,
+    /// Frequency the sample was recorded at
+    pub root_frequency: f32,
+    /// Lowest and highest MIDI note numbers this zone plays
+    pub keys: (i32, i32),
+    /// Sustain loop as start and end frames; without one the sample plays once
+    pub loop_points: Option<(usize, usize)>,
+}
+
+impl SampleZone {
+    /// A zone covering every key, recorded at `root` in `octave`
+    pub fn new(data: Arc<SampleData>, root: Note, octave: u8) -> Self {
+        Self {
+            data,
+            root_frequency: root.frequency(octave),
+            keys: (0, 127),
+            loop_points: None,
+        }
+    }
+
+    pub fn with_keys(mut self, low: i32, high: i32) -> Self {
+        self.keys = (low.min(high), low.max(high));
+        self
+    }
+
+    pub fn with_loop(mut self, start: usize, end: usize) -> Result<Self, SoundError> {
+        if start >= end || end > self.data.samples.len() {
+            return Err(SoundError::OutOfRange(format!(
+                "Loop {}-{} does not fit a sample of {} frames",
+                start,
+                end,
+                self.data.samples.len()
+            )));
+        }
+        self.loop_points = Some((start, end));
+        Ok(self)
+    }
+
+    /// Distance in semitones from this zone's key range
+    fn distance(&self, key: i32) -> i32 {
+        (self.keys.0 - key).max(key - self.keys.1).max(0)
+    }
+}
+
+/// A set of zones; each note plays the zone covering its key
+#[derive(Debug, Clone)]
+pub struct SampledInstrument {
+    pub zones: Vec<SampleZone>,
+}
+
+impl SampledInstrument {
+    /// Parse zones separated by `;`, each `file[,root=C4][,keys=C0-B3][,loop=start-end]`
+    pub fn parse(spec: &str) -> Result<Self, SoundError> {
+        let zones = spec
+            .split(';')
+            .map(parse_zone)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { zones })
+    }
+
+    /// Index of the zone for a key, falling back to the nearest range
+    fn zone_for(&self, key: i32) -> usize {
+        (0..self.zones.len())
+            .min_by_key(|&i| self.zones[i].distance(key))
+            .unwrap_or(0)
+    }
+}
+
+fn parse_zone(spec: &str) -> Result<SampleZone, SoundError> {
+    let mut parts = spec.split(',');
+    let path = parts.next().unwrap_or_default().trim();
+    let data = Arc::new(SampleData::load(path)?);
+
+    let mut root = (Note::C, 4);
+    let mut keys = None;
+    let mut loop_points = None;
+    for part in parts {
+        let (name, value) = part.split_once('=').ok_or_else(|| {
+            SoundError::Invalid(format!(
+                "Expected name=value in sample zone, got '{}'",
+                part
+            ))
+        })?;
+        let (name, value) = (name.trim(), value.trim());
+        let range = || {
+            value
+                .split_once('-')
+                .ok_or_else(|| format!("Expected a range like low-high, got '{}'", value))
+        };
+        match name {
+            "root" => root = parse_note_with_octave(value)?,
+            "keys" => {
+                let (low, high) = range()?;
+                let (low, high) = (parse_note_with_octave(low)?, parse_note_with_octave(high)?);
+                keys = Some((low.0.midi_number(low.1), high.0.midi_number(high.1)));
+            }
+            "loop" => {
+                let (start, end) = range()?;
+                let frame = |v: &str| {
+                    v.parse::<usize>()
+                        .map_err(|_| format!("Invalid loop point '{}'", v))
+                };
+                loop_points = Some((frame(start)?, frame(end)?));
+            }
+            _ => {
+                return Err(SoundError::Invalid(format!(
+                    "Unknown sample zone setting '{}'",
+                    name
+                )))
+            }
+        }
+    }
+
+    let mut zone = SampleZone::new(data, root.0, root.1);
+    if let Some((low, high)) = keys {
+        zone = zone.with_keys(low, high);
+    }
+    if let Some((start, end)) = loop_points {
+        zone = zone.with_loop(start, end)?;
+    }
+    Ok(zone)
+}
+
+/// Nearest MIDI note number for a frequency
+fn midi_from_frequency(frequency: f32) -> i32 {
+    (69.0 + 12.0 * (frequency / 440.0).log2()).round() as i32
+}
+
+/// Plays a [`SampledInstrument`].
+///
+/// The sample is read at its own rate, so the phase passed in by the note
+/// source is not used.
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    instrument: Arc<SampledInstrument>,
+    zone: usize,
+    position: f32,
+    step: f32,
+}
+
+impl Sampler {
+    pub fn new(instrument: Arc<SampledInstrument>) -> Self {
+        Self {
+            instrument,
+            zone: 0,
+            position: 0.0,
+            step: 0.0,
+        }
+    }
+}
+
+impl Oscillator for Sampler {
+    fn start(&mut self, frequency: f32, sample_rate: u32) {
+        self.zone = self.instrument.zone_for(midi_from_frequency(frequency));
+        let zone = &self.instrument.zones[self.zone];
+        self.step =
+            frequency / zone.root_frequency * zone.data.sample_rate as f32 / sample_rate as f32;
+        self.position = 0.0;
+    }
+
+    fn sample(&mut self, _phase: f32) -> f32 {
+        let zone = &self.instrument.zones[self.zone];
+        let samples = &zone.data.samples;
+
+        if let Some((start, end)) = zone.loop_points {
+            // A high note can step past the loop more than once in one sample
+            if self.position >= end as f32 {
+                let (start, end) = (start as f32, end as f32);
+                self.position = start + (self.position - start) % (end - start);
+            }
+        }
+
+        let index = self.position as usize;
+        if index >= samples.len() {
+            return 0.0;
+        }
+        let fraction = self.position.fract();
+        let next = samples.get(index + 1).copied().unwrap_or(0.0);
+        let sample = samples[index] * (1.0 - fraction) + next * fraction;
+
+        self.position += self.step;
+        sample
+    }
+}
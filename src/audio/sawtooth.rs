@@ -0,0 +1,13 @@
+//! Sawtooth wave oscillator
+
+use super::oscillator::Oscillator;
+
+/// Sawtooth wave
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sawtooth;
+
+impl Oscillator for Sawtooth {
+    fn sample(&mut self, phase: f32) -> f32 {
+        2.0f32 * (phase - (phase + 0.5f32).floor())
+    }
+}
@@ -0,0 +1,14 @@
+//! Sine wave oscillator
+
+use super::oscillator::Oscillator;
+use std::f32::consts::TAU;
+
+/// Sine wave
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sine;
+
+impl Oscillator for Sine {
+    fn sample(&mut self, phase: f32) -> f32 {
+        (TAU * phase).sin()
+    }
+}
@@ -0,0 +1,18 @@
+//! Stereo placement of mono voices
+
+use rodio::{source::ChannelVolume, Source};
+use std::f32::consts::FRAC_PI_4;
+
+/// Left and right gains for a pan position from -1.0 (hard left) to 1.0 (hard right).
+///
+/// Uses a constant-power law so a voice keeps the same loudness as it moves.
+pub fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+    (angle.cos(), angle.sin())
+}
+
+/// Place a mono source in the stereo field
+pub fn pan<S: Source>(source: S, pan: f32) -> ChannelVolume<S> {
+    let (left, right) = pan_gains(pan);
+    ChannelVolume::new(source, vec![left, right])
+}
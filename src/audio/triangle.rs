@@ -1,71 +1,13 @@
-//! Audio generation and square wave synthesis
+//! Triangle wave oscillator
 
-use crate::music::note::MusicNote;
-use rodio::{source::Function, Source};
-use std::time::Duration;
+use super::oscillator::Oscillator;
 
-/// Square wave audio generator
-pub struct Triangle {
-    frequency: f32,
-    sample_rate: u32,
-    phase: f32,
-    duration: Duration,
-    samples_played: usize,
-    total_samples: usize,
-}
-
-impl Triangle {
-    pub fn new(frequency: f32, sample_rate: u32, duration: Duration) -> Self {
-        let total_samples = (duration.as_secs_f32() * sample_rate as f32) as usize;
-        Self {
-            frequency,
-            sample_rate,
-            phase: 0.0,
-            duration,
-            samples_played: 0,
-            total_samples,
-        }
-    }
-
-    pub fn from_note(note: &MusicNote, sample_rate: u32) -> Self {
-        Self::new(note.frequency(), sample_rate, note.duration)
-    }
-}
-
-impl Iterator for Triangle {
-    type Item = f32;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.samples_played >= self.total_samples {
-            return None; // Note duration completed
-        }
-
-        let sample = 4.0f32 * (self.phase - (self.phase + 0.5f32).floor()).abs() - 1f32;
-
-        self.phase += self.frequency / self.sample_rate as f32;
-        if self.phase >= 1.0 {
-            self.phase -= 1.0;
-        }
-
-        self.samples_played += 1;
-        Some(sample)
-    }
-}
-
-impl Source for Triangle {
-    fn current_span_len(&self) -> Option<usize> {
-        Some(self.total_samples - self.samples_played)
-    }
-
-    fn channels(&self) -> u16 {
-        1
-    }
-
-    fn sample_rate(&self) -> u32 {
-        self.sample_rate
-    }
+/// Triangle wave
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Triangle;
 
-    fn total_duration(&self) -> Option<Duration> {
-        Some(self.duration)
+impl Oscillator for Triangle {
+    fn sample(&mut self, phase: f32) -> f32 {
+        4.0f32 * (phase - (phase + 0.5f32).floor()).abs() - 1f32
     }
 }
@@ -1,144 +1,98 @@
-//! Audio generation and square wave synthesis
-
-use crate::music::note::MusicNote;
-use rodio::Source;
-use std::f32::consts::TAU;
-use std::time::Duration;
+//! Selectable wave types
+
+use super::{
+    additive::Additive,
+    fm::{Fm, FmSettings},
+    oscillator::{NoteSource, Oscillator},
+    pulse::Pulse,
+    sampler::{SampledInstrument, Sampler},
+    sawtooth::Sawtooth,
+    sine::Sine,
+    square::Square,
+    triangle::Triangle,
+    wavetable::{Wavetable, WavetableOscillator},
+};
+use crate::error::SoundError;
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
 pub enum WaveType {
     Sine,
     Square,
     Triangle,
     Pulse,
     Sawtooth,
+    /// Single-cycle waveform loaded from a file
+    Table(Arc<Wavetable>),
+    /// Additive synthesis with per-partial amplitudes
+    Harmonics(Arc<[f32]>),
+    /// Frequency modulation stack
+    Fm(Arc<FmSettings>),
+    /// Recorded samples repitched to each note
+    Sampled(Arc<SampledInstrument>),
 }
 
-pub fn get_wave_type(wave_type: &str) -> WaveType {
-    match wave_type.to_lowercase().as_str() {
-        "sine" => WaveType::Sine,
-        "square" => WaveType::Square,
-        "triangle" => WaveType::Triangle,
-        "pulse" => WaveType::Pulse,
-        "sawtooth" => WaveType::Sawtooth,
-        _ => WaveType::Sine,
-    }
-}
-
-/// Square wave audio generator
-pub struct Wave {
-    wave_type: WaveType,
-    sample_rate: u32,
-    phase: f32,
-    phase_step: f32,
-    samples_played: usize,
-    limit: Option<usize>,
-}
-
-impl Wave {
-    pub fn finite(
-        wave_type: WaveType,
-        frequency: f32,
-        sample_rate: u32,
-        duration: Duration,
-    ) -> Self {
-        let period = sample_rate as f32 / frequency;
-        let phase_step = 1.0f32 / period;
-        let total_samples = (duration.as_secs_f32() * sample_rate as f32) as usize;
-        Self {
-            wave_type,
-            sample_rate,
-            phase: 0.0,
-            phase_step,
-            samples_played: 0,
-            limit: Some(total_samples),
+impl WaveType {
+    /// A fresh oscillator for one note of this wave type
+    pub fn oscillator(&self) -> Box<dyn Oscillator> {
+        match self {
+            WaveType::Sine => Box::new(Sine),
+            WaveType::Square => Box::new(Square),
+            WaveType::Triangle => Box::new(Triangle),
+            WaveType::Pulse => Box::new(Pulse::default()),
+            WaveType::Sawtooth => Box::new(Sawtooth),
+            WaveType::Table(table) => Box::new(WavetableOscillator::new(table.clone())),
+            WaveType::Harmonics(amplitudes) => Box::new(Additive::new(amplitudes.clone())),
+            WaveType::Fm(settings) => Box::new(Fm::new(settings.clone())),
+            WaveType::Sampled(instrument) => Box::new(Sampler::new(instrument.clone())),
         }
     }
+}
 
-    pub fn infinite(wave_type: WaveType, frequency: f32, sample_rate: u32) -> Self {
-        let period = sample_rate as f32 / frequency;
-        let phase_step = 1.0f32 / period;
-        Self {
-            wave_type,
-            sample_rate,
-            phase: 0.0,
-            phase_step,
-            samples_played: 0,
-            limit: None,
-        }
-    }
-
-    pub fn from_note(wave_type: WaveType, note: &MusicNote, sample_rate: u32) -> Self {
-        Self::finite(wave_type, note.frequency(), sample_rate, note.duration)
-    }
-
-    fn sine(&self) -> f32 {
-        (TAU * self.phase).sin()
-    }
-
-    fn triangle(&self) -> f32 {
-        4.0f32 * (self.phase - (self.phase + 0.5f32).floor()).abs() - 1f32
-    }
-
-    fn square(&self) -> f32 {
-        if self.phase % 1.0f32 < 0.5f32 {
-            1.0f32
-        } else {
-            -1.0f32
-        }
+/// Parse a wave name, `table:<file>`, `harmonics:<a1>,<a2>,...`, `fm:<stack>` or `sample:<zones>`
+pub fn get_wave_type(wave_type: &str) -> Result<WaveType, SoundError> {
+    if let Some((kind, spec)) = wave_type.split_once(':') {
+        return match kind.to_lowercase().as_str() {
+            "table" => Ok(WaveType::Table(Arc::new(Wavetable::load(spec)?))),
+            "harmonics" => Ok(WaveType::Harmonics(parse_amplitudes(spec)?.into())),
+            "fm" => Ok(WaveType::Fm(Arc::new(FmSettings::parse(spec)?))),
+            "sample" => Ok(WaveType::Sampled(Arc::new(SampledInstrument::parse(spec)?))),
+            _ => Err(SoundError::Invalid(format!(
+                "Unknown wave '{}'. Use table:<file>, harmonics:<amplitudes>, fm:<stack> or sample:<file>",
+                kind
+            ))),
+        };
     }
 
-    fn pulse(&self) -> f32 {
-        if self.phase % 1.0f32 < 0.25f32 {
-            1.0f32
-        } else {
-            -1.0f32
+    Ok(match wave_type.to_lowercase().as_str() {
+        "sine" => WaveType::Sine,
+        "square" => WaveType::Square,
+        "triangle" => WaveType::Triangle,
+        "pulse" => WaveType::Pulse,
+        "sawtooth" => WaveType::Sawtooth,
+        _ => {
+            return Err(SoundError::Invalid(format!(
+                "Unknown wave '{}'. Try: sine, square, triangle, pulse, sawtooth",
+                wave_type
+            )))
         }
-    }
-
-    fn sawtooth(&self) -> f32 {
-        2.0f32 * (self.phase - (self.phase + 0.5f32).floor())
-    }
+    })
 }
 
-impl Iterator for Wave {
-    type Item = f32;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Some(limit) = self.limit {
-            if self.samples_played >= limit {
-                return None;
-            }
-        }
-
-        let sample = match self.wave_type {
-            WaveType::Sine => self.sine(),
-            WaveType::Triangle => self.triangle(),
-            WaveType::Square => self.square(),
-            WaveType::Pulse => self.pulse(),
-            WaveType::Sawtooth => self.sawtooth(),
-        };
-
-        self.phase = (self.phase + self.phase_step).rem_euclid(1.0f32);
-
-        self.samples_played += 1;
-        Some(sample)
+fn parse_amplitudes(spec: &str) -> Result<Vec<f32>, String> {
+    let amplitudes = spec
+        .split(',')
+        .map(|a| {
+            a.trim()
+                .parse::<f32>()
+                .map_err(|_| format!("Invalid harmonic amplitude '{}'", a))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if amplitudes.iter().all(|a| *a == 0.0) {
+        return Err("Harmonics need at least one non-zero amplitude".to_string());
     }
+    Ok(amplitudes)
 }
 
-impl Source for Wave {
-    fn current_span_len(&self) -> Option<usize> {
-        self.limit.map(|lim| lim - self.samples_played)
-    }
-
-    fn channels(&self) -> u16 {
-        1
-    }
-
-    fn sample_rate(&self) -> u32 {
-        self.sample_rate
-    }
-
-    fn total_duration(&self) -> Option<Duration> {
-        self.limit
-            .map(|lim| Duration::from_secs_f32(lim as f32 / self.sample_rate as f32))
-    }
-}
+/// A note source playing one of the selectable wave types
+pub type Wave = NoteSource<Box<dyn Oscillator>>;
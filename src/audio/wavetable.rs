@@ -0,0 +1,107 @@
+//! Wavetable oscillator playing a stored single-cycle waveform
+
+use super::oscillator::Oscillator;
+use crate::error::SoundError;
+use rodio::{Decoder, Source};
+use std::f32::consts::TAU;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Length of tables built from harmonics
+const TABLE_SIZE: usize = 2048;
+
+/// One cycle of a waveform, normalized to a peak of 1.0
+#[derive(Debug, Clone)]
+pub struct Wavetable {
+    samples: Vec<f32>,
+}
+
+impl Wavetable {
+    pub fn from_samples(mut samples: Vec<f32>) -> Result<Self, SoundError> {
+        let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        if peak == 0.0 {
+            return Err(SoundError::Invalid(
+                "Wavetable is empty or silent".to_string(),
+            ));
+        }
+        for sample in &mut samples {
+            *sample /= peak;
+        }
+        Ok(Self { samples })
+    }
+
+    /// Build a table by summing sine partials; `amplitudes[0]` is the fundamental
+    pub fn from_harmonics(amplitudes: &[f32]) -> Result<Self, SoundError> {
+        let samples = (0..TABLE_SIZE)
+            .map(|i| {
+                let phase = i as f32 / TABLE_SIZE as f32;
+                amplitudes
+                    .iter()
+                    .enumerate()
+                    .map(|(k, a)| a * (TAU * (k + 1) as f32 * phase).sin())
+                    .sum()
+            })
+            .collect();
+        Self::from_samples(samples)
+    }
+
+    /// Load a single-cycle waveform from an audio file (WAV, FLAC, ...)
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SoundError> {
+        let path = path.as_ref();
+        let (samples, _sample_rate) = load_mono(path)?;
+        Self::from_samples(samples).map_err(|error| SoundError::Io {
+            path: path.display().to_string(),
+            message: error.to_string(),
+        })
+    }
+
+    /// Linearly interpolated value at `phase` (0.0 to 1.0)
+    fn at(&self, phase: f32) -> f32 {
+        let position = phase * self.samples.len() as f32;
+        let index = position as usize % self.samples.len();
+        let next = (index + 1) % self.samples.len();
+        let fraction = position.fract();
+        self.samples[index] * (1.0 - fraction) + self.samples[next] * fraction
+    }
+}
+
+/// Decode an audio file and mix it down to mono, returning the samples and their rate
+pub(crate) fn load_mono<P: AsRef<Path>>(path: P) -> Result<(Vec<f32>, u32), SoundError> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|error| SoundError::Io {
+        path: path.display().to_string(),
+        message: format!("cannot read: {}", error),
+    })?;
+    let decoder = Decoder::try_from(file).map_err(|error| SoundError::Io {
+        path: path.display().to_string(),
+        message: format!("cannot decode: {}", error),
+    })?;
+
+    let channels = decoder.channels().max(1) as usize;
+    let sample_rate = decoder.sample_rate();
+    let interleaved: Vec<f32> = decoder.collect();
+    let samples = interleaved
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    Ok((samples, sample_rate))
+}
+
+/// Plays a shared [`Wavetable`]
+#[derive(Debug, Clone)]
+pub struct WavetableOscillator {
+    table: Arc<Wavetable>,
+}
+
+impl WavetableOscillator {
+    pub fn new(table: Arc<Wavetable>) -> Self {
+        Self { table }
+    }
+}
+
+impl Oscillator for WavetableOscillator {
+    fn sample(&mut self, phase: f32) -> f32 {
+        self.table.at(phase)
+    }
+}
@@ -0,0 +1,113 @@
+use super::args::AnalyzeArgs;
+use super::play::{meter_from_args, scale_from_args};
+use crate::error::SoundError;
+use crate::music::analysis::{analyze as rank, PitchProfile};
+use crate::music::midi::MidiFile;
+use crate::music::parse_score_in_meter;
+use crate::music::spelling::KeySpelling;
+use crate::{parse_key, parse_note_notation_in_meter};
+use std::fs;
+use std::path::Path;
+
+/// General MIDI percussion, which has no pitch
+const DRUM_CHANNEL: u8 = 9;
+
+pub fn analyze(analyze_args: &AnalyzeArgs) -> Result<(), SoundError> {
+    let args = &analyze_args.args;
+    let profile = match args.notes.as_slice() {
+        [path] if Path::new(path).is_file() => profile_from_file(analyze_args, path)?,
+        _ => {
+            let scale_len = scale_from_args(args)?.len();
+            let (meter, symbol_ticks) = meter_from_args(args)?;
+            let elements =
+                parse_note_notation_in_meter(&args.notes, scale_len, meter, symbol_ticks)?;
+            profile_from_notation(analyze_args, &elements)?
+        }
+    };
+    if profile.is_empty() {
+        return Err(SoundError::Invalid(
+            "No pitched notes to analyze".to_string(),
+        ));
+    }
+
+    println!("🔎 Pitch content:");
+    for (note, share) in profile.used() {
+        println!("  {:<3} {:>5.1}%", note.to_string(), share * 100.0);
+    }
+
+    println!();
+    println!("🏆 Best fits:");
+    for (rank, candidate) in rank(&profile, &args.scales)
+        .iter()
+        .take(analyze_args.top)
+        .enumerate()
+    {
+        let intervals = &args.scales.get(&candidate.scale_id)?.intervals;
+        let spelling = KeySpelling::for_note(candidate.root, intervals);
+        let outside = if candidate.outside.is_empty() {
+            String::new()
+        } else {
+            let notes: Vec<String> = candidate
+                .outside
+                .iter()
+                .map(|&note| spelling.name_note(note).to_string())
+                .collect();
+            format!("  outside: {}", notes.join(" "))
+        };
+        println!(
+            "  {}. {:<24} {:>5.1}%  (--key {} --scale {}){}",
+            rank + 1,
+            format!("{} {}", spelling.root(), candidate.scale_name),
+            candidate.fit * 100.0,
+            spelling.root(),
+            candidate.scale_id,
+            outside
+        );
+    }
+
+    Ok(())
+}
+
+/// Degrees are read in the --key and --scale they were written for
+fn profile_from_notation(
+    analyze_args: &AnalyzeArgs,
+    elements: &[crate::NoteElement],
+) -> Result<PitchProfile, SoundError> {
+    let args = &analyze_args.args;
+    let scale = scale_from_args(args)?;
+    let (root, _) = parse_key(&args.key)?;
+    Ok(PitchProfile::from_elements(
+        elements,
+        &scale.intervals,
+        root.note(),
+    ))
+}
+
+fn profile_from_file(analyze_args: &AnalyzeArgs, path: &str) -> Result<PitchProfile, SoundError> {
+    let extension = Path::new(path)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    if matches!(extension.as_deref(), Some("mid" | "midi")) {
+        let midi = MidiFile::load(path)?;
+        let mut profile = PitchProfile::default();
+        for note in midi.notes.iter().filter(|n| n.channel != DRUM_CHANNEL) {
+            // Zero-length notes still count a little
+            profile.add(note.key as i32, note.length.max(1) as f32);
+        }
+        return Ok(profile);
+    }
+
+    let text = fs::read_to_string(path).map_err(|error| SoundError::Io {
+        path: path.to_string(),
+        message: format!("cannot read: {}", error),
+    })?;
+    let (meter, symbol_ticks) = meter_from_args(&analyze_args.args)?;
+    let scale_len = scale_from_args(&analyze_args.args)?.len();
+    let score = parse_score_in_meter(&text, scale_len, meter, symbol_ticks)?;
+    let elements: Vec<_> = score
+        .tracks()
+        .into_iter()
+        .flat_map(|track| track.note_elements)
+        .collect();
+    profile_from_notation(analyze_args, &elements)
+}
@@ -1,3 +1,4 @@
+use crate::music::scale::ScaleRegistry;
 use clap::Parser;
 
 /// Sound CLI - Generate and play melodies with customizable scales and keys
@@ -9,23 +10,47 @@ use clap::Parser;
 pub struct Args {
     /// Enhanced note notation with rests, sustains, and modal octave shifts
     #[arg(
-        help = "Enhanced note notation: digits 1-9 for scale positions, dots (.) for rests, dashes (-) extend notes. Each symbol defaults to sixteenth note duration (use --duration to change). Octave shifts: ^ (up) and v (down) change register for all following notes. Examples: 12345 (notes 1-5), 1^234 (note 1 normal, then shift up, notes 2-4 higher)"
+        help = "Enhanced note notation: digits 1-9 for scale positions, dots (.) for rests, dashes (-) extend notes. Each symbol defaults to sixteenth note duration (use --duration to change). Octave shifts: ^ (up) and v (down) change register for all following notes. A ~ ties a note to the next one of the same pitch, ' after a note plays it staccato and _ legato. Bar lines (|) are checked against --meter. Repeats: |: ... :| (x3 for three passes) with endings [1 [2; phrases: $riff = 1235- then $riff. Examples: 12345 (notes 1-5), 1^234 (note 1 normal, then shift up, notes 2-4 higher)"
     )]
     pub notes: Vec<String>,
 
     /// Scale to use for the melody
     #[arg(short, long, default_value = "major")]
-    #[arg(help = "Scale: major, minor, dorian, blues, japanese, etc.")]
+    #[arg(
+        help = "Scale: major, minor, dorian, blues, japanese, etc. Custom scales can be defined in the config file"
+    )]
     pub scale: String,
 
+    /// Custom scale intervals, replacing --scale
+    #[arg(long)]
+    #[arg(
+        help = "Custom scale as semitones from the root, e.g. 0,2,3,7,8 (the octave is added automatically); replaces --scale"
+    )]
+    pub scale_intervals: Option<String>,
+
+    /// Known scales, including any defined in config files
+    #[arg(skip)]
+    pub scales: ScaleRegistry,
+
     /// Wave form to use
     #[arg(short, long, default_value = "sine")]
-    #[arg(help = "sine, triangle, square, pulse, sawtooth")]
+    #[arg(
+        help = "sine, triangle, square, pulse, sawtooth, table:<file.wav> (single-cycle wavetable), harmonics:1,0.5,0.33 (additive partial amplitudes), fm:bell, fm:epiano, fm:1,3.5*5 (FM operator ratios*indexes, carrier first), or sample:<file.wav>[,root=C4][,keys=C0-B3][,loop=start-end] (zones separated by ;)"
+    )]
     pub wave: String,
 
+    /// Instrument preset file
+    #[arg(short, long)]
+    #[arg(
+        help = "Instrument preset (TOML) with wave, envelope, filter, effects and gain; replaces --wave"
+    )]
+    pub instrument: Option<String>,
+
     /// Key/root note for the melody
     #[arg(short, long, default_value = "C")]
-    #[arg(help = "Root note: C, D, E, F, G, A, B (with optional # for sharps)")]
+    #[arg(
+        help = "Root note: C, D, E, F, G, A, B with optional sharps (#) or flats (b), e.g. F#, Bb, Ebb, and an optional octave (default 4), e.g. Eb3"
+    )]
     pub key: String,
 
     /// Tempo in beats per minute (quarter note = 1 beat)
@@ -44,4 +69,160 @@ pub struct Args {
         help = "Duration each note symbol represents: whole/1, half/2, quarter/4, eighth/8, sixteenth/16"
     )]
     pub duration: String,
+
+    /// Time signature for bar lines
+    #[arg(short, long, default_value = "4/4")]
+    #[arg(help = "Meter that bar lines (|) in the notation are checked against, e.g. 3/4 or 6/8")]
+    pub meter: String,
+
+    /// Share of each note's length that sounds
+    #[arg(short, long, default_value = "1.0")]
+    #[arg(
+        help = "How much of its length each note sounds, from just above 0 to 1 (legato); a note marked ' (staccato) sounds half, _ (legato) all of it"
+    )]
+    pub gate: f32,
+
+    /// Feedback delay synced to the tempo
+    #[arg(long)]
+    #[arg(help = "Add a feedback delay synced to the tempo (dotted eighth repeats)")]
+    pub delay: bool,
+
+    /// Reverb
+    #[arg(long)]
+    #[arg(help = "Add a room reverb")]
+    pub reverb: bool,
+
+    /// Chorus
+    #[arg(long)]
+    #[arg(help = "Add a chorus to thicken the sound")]
+    pub chorus: bool,
+
+    /// Render to a file instead of playing
+    #[arg(short, long)]
+    #[arg(help = "Write the result to a WAV file instead of playing it")]
+    pub output: Option<String>,
+
+    /// Audio output device
+    #[arg(long)]
+    #[arg(help = "Name (or part of the name) of the audio output device to use")]
+    pub device: Option<String>,
+
+    /// Audio buffer size in frames
+    #[arg(long)]
+    #[arg(help = "Audio buffer size in frames; smaller is more responsive but may crackle")]
+    pub buffer_size: Option<u32>,
+
+    /// Keyboard layout for jam mode
+    #[arg(long)]
+    #[arg(
+        help = "Jam keyboard layout: qwerty, azerty, qwertz, dvorak, or up to four rows of keys separated by spaces, e.g. \"1234567890 qwertyuiop asdfghjkl; zxcvbnm,./\""
+    )]
+    pub layout: Option<String>,
+}
+
+/// Arguments for the `scales` command
+#[derive(clap::Args, Debug, Clone)]
+pub struct ScalesArgs {
+    /// Scale to audition
+    #[arg(long, value_name = "SCALE")]
+    #[arg(help = "Play this scale up and down instead of listing scales")]
+    pub play: Option<String>,
+
+    #[command(flatten)]
+    pub args: Args,
+}
+
+/// Arguments for the `analyze` command
+#[derive(clap::Args, Debug, Clone)]
+pub struct AnalyzeArgs {
+    /// Number of candidates to show
+    #[arg(long, default_value = "5")]
+    #[arg(help = "How many of the best-fitting keys and scales to show")]
+    pub top: usize,
+
+    #[command(flatten)]
+    pub args: Args,
+}
+
+/// Arguments for the `transform` command; operations apply in the order listed
+#[derive(clap::Args, Debug, Clone)]
+pub struct TransformArgs {
+    /// Move to another key
+    #[arg(long, value_name = "KEY")]
+    #[arg(help = "Transpose to another key (the degrees stay the same)")]
+    pub transpose: Option<String>,
+
+    /// Move to another scale or mode
+    #[arg(long, value_name = "SCALE")]
+    #[arg(
+        help = "Play the melody in another scale: same degrees for scales of the same size, nearest notes otherwise"
+    )]
+    pub mode: Option<String>,
+
+    /// Diatonic shift
+    #[arg(long, value_name = "STEPS", allow_negative_numbers = true)]
+    #[arg(help = "Move every note up (or down, if negative) by scale steps")]
+    pub steps: Option<i32>,
+
+    /// Mirror around the first note
+    #[arg(long)]
+    #[arg(help = "Invert the melody around its first note")]
+    pub invert: bool,
+
+    /// Cycle pitches over the rhythm
+    #[arg(long, value_name = "NOTES", allow_negative_numbers = true)]
+    #[arg(help = "Keep the rhythm and move the pitches this many notes along it")]
+    pub rotate: Option<i32>,
+
+    /// Reverse in time
+    #[arg(long)]
+    #[arg(help = "Play the melody backwards")]
+    pub retrograde: bool,
+
+    /// Lengthen notes
+    #[arg(long, value_name = "FACTOR")]
+    #[arg(help = "Multiply every note and rest length by this factor")]
+    pub augment: Option<usize>,
+
+    /// Shorten notes
+    #[arg(long, value_name = "FACTOR")]
+    #[arg(help = "Divide every note and rest length by this factor (at least one symbol each)")]
+    pub diminish: Option<usize>,
+
+    #[command(flatten)]
+    pub args: Args,
+}
+
+/// Arguments for the `parse` command
+#[derive(clap::Args, Debug, Clone)]
+pub struct ParseArgs {
+    /// Print JSON instead of Rust debug output
+    #[arg(long)]
+    #[arg(help = "Print the parsed structure as JSON (needs the serde feature)")]
+    pub json: bool,
+
+    #[command(flatten)]
+    pub args: Args,
+}
+
+/// Arguments for the `fmt` command
+#[derive(clap::Args, Debug, Clone)]
+pub struct FmtArgs {
+    /// Symbols per bar
+    #[arg(long, value_name = "SYMBOLS")]
+    #[arg(help = "Replace the bar lines with one every this many symbols")]
+    pub bar: Option<usize>,
+
+    /// Rewrite the file in place
+    #[arg(long)]
+    #[arg(help = "Write the formatted score back to the file instead of printing it")]
+    pub write: bool,
+
+    /// Print ABC notation instead
+    #[arg(long, conflicts_with = "write")]
+    #[arg(help = "Print the notes as an ABC tune in the key, scale, meter and tempo given")]
+    pub abc: bool,
+
+    #[command(flatten)]
+    pub args: Args,
 }
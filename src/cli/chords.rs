@@ -0,0 +1,50 @@
+use super::args::Args;
+use super::jam::{build_diatonic_triad, print_chord_progression, triad_quality};
+use super::play::scale_from_args;
+use crate::error::SoundError;
+use crate::music::spelling::KeySpelling;
+use crate::{parse_key, Key};
+
+const NUMERALS: [&str; 12] = [
+    "I", "II", "III", "IV", "V", "VI", "VII", "VIII", "IX", "X", "XI", "XII",
+];
+
+pub fn chords(args: &Args) -> Result<(), SoundError> {
+    let scale = scale_from_args(args)?;
+    let (root, octave) = parse_key(&args.key)?;
+    let key = Key::new(root.note(), octave);
+    let spelling = KeySpelling::new(root, &scale.intervals);
+
+    println!("🎸 Diatonic chords in {} {}:", root, scale.name);
+    for (degree, numeral) in NUMERALS.iter().enumerate().take(scale.len()) {
+        let (intervals, _) = build_diatonic_triad(&scale.intervals, degree);
+        let quality = triad_quality(
+            (intervals[1] - intervals[0]).rem_euclid(12),
+            (intervals[2] - intervals[0]).rem_euclid(12),
+        );
+
+        // Minor and diminished chords get lower-case numerals
+        let numeral = match quality {
+            "m" | "°" => numeral.to_lowercase(),
+            _ => numeral.to_string(),
+        };
+        let numeral = format!("{}{}", numeral, quality.trim_start_matches('m'));
+        let notes: Vec<String> = intervals
+            .iter()
+            .map(|&interval| spelling.name(interval).to_string())
+            .collect();
+
+        println!(
+            "  {:<6} {:<6} {}",
+            numeral,
+            format!("{}{}", spelling.name(intervals[0]), quality),
+            notes.join(" ")
+        );
+    }
+
+    println!();
+    println!("🎹 Chord keys in jam mode:");
+    print_chord_progression(&key, &scale.intervals, &args.scale);
+
+    Ok(())
+}
@@ -1,5 +1,20 @@
-use super::{args::Args, jam::jam, play::play, read::read};
-use clap::{Parser, Subcommand};
+use super::{
+    analyze::analyze,
+    args::{AnalyzeArgs, Args, FmtArgs, ParseArgs, ScalesArgs, TransformArgs},
+    chords::chords,
+    config::Config,
+    fmt::fmt,
+    jam::jam,
+    parse::parse,
+    play::{play, print_examples},
+    read::read,
+    scales::scales,
+    transform::transform,
+};
+use crate::error::SoundError;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::fs;
+use std::path::Path;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -16,29 +31,138 @@ struct Cli {
 enum Commands {
     /// Play a melody
     Play(Args),
-    /// Read a melody from a file
+    /// Read a score or an ABC tune from a file
     Read(Args),
     /// Jam a melody
     Jam(Args),
+    /// List every scale with its notes, or audition one with --play
+    Scales(ScalesArgs),
+    /// Show the diatonic chords of a key and scale
+    Chords(Args),
+    /// Find the keys and scales that best fit a melody, score or MIDI file
+    Analyze(AnalyzeArgs),
+    /// Rework a melody (transpose, change mode, invert, reverse, stretch) and print the new notation
+    Transform(TransformArgs),
+    /// Print the parsed notes or score, as JSON with --json
+    Parse(ParseArgs),
+    /// Rewrite notes or a score file in canonical notation, with voices aligned
+    Fmt(FmtArgs),
 }
 
 pub fn run_cli() {
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+
+    // Config files fill in whatever was not given on the command line
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("❌ Error in config: {}", error);
+            std::process::exit(1);
+        }
+    };
+    let arg_matches = matches.subcommand().map_or(&matches, |(_, sub)| sub);
 
     match cli.command {
-        Some(Commands::Play(args)) => {
-            play(&args);
+        Some(Commands::Play(mut args)) => {
+            config.apply(&mut args, arg_matches);
+            run_play(&args);
         }
-        Some(Commands::Read(args)) => {
-            read(&args);
+        Some(Commands::Read(mut args)) => {
+            config.apply(&mut args, arg_matches);
+            if let Err(error) = read(&args) {
+                report(&error, &args);
+                std::process::exit(1);
+            }
         }
-        Some(Commands::Jam(args)) => {
+        Some(Commands::Jam(mut args)) => {
+            config.apply(&mut args, arg_matches);
             if let Err(e) = jam(&args) {
                 eprintln!("Error in jam mode: {}", e);
             }
         }
+        Some(Commands::Scales(mut scales_args)) => {
+            config.apply(&mut scales_args.args, arg_matches);
+            if let Err(error) = scales(&scales_args) {
+                eprintln!("❌ Error: {}", error);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Chords(mut args)) => {
+            config.apply(&mut args, arg_matches);
+            if let Err(error) = chords(&args) {
+                eprintln!("❌ Error: {}", error);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Analyze(mut analyze_args)) => {
+            config.apply(&mut analyze_args.args, arg_matches);
+            if let Err(error) = analyze(&analyze_args) {
+                report(&error, &analyze_args.args);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Transform(mut transform_args)) => {
+            config.apply(&mut transform_args.args, arg_matches);
+            if let Err(error) = transform(&transform_args) {
+                report(&error, &transform_args.args);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Parse(mut parse_args)) => {
+            config.apply(&mut parse_args.args, arg_matches);
+            if let Err(error) = parse(&parse_args) {
+                report(&error, &parse_args.args);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Fmt(mut fmt_args)) => {
+            config.apply(&mut fmt_args.args, arg_matches);
+            if let Err(error) = fmt(&fmt_args) {
+                report(&error, &fmt_args.args);
+                std::process::exit(1);
+            }
+        }
         None => {
-            play(&cli.args);
+            let mut args = cli.args;
+            config.apply(&mut args, arg_matches);
+            run_play(&args);
         }
     }
 }
+
+/// Play from the command line, with examples if the melody does not parse
+fn run_play(args: &Args) {
+    if let Err(error) = play(args) {
+        report(&error, args);
+        if !matches!(error, SoundError::Audio(_) | SoundError::Io { .. }) {
+            print_examples();
+        }
+        std::process::exit(1);
+    }
+}
+
+/// Print an error, quoting the notation it points at: the score file when
+/// the only argument is a file, otherwise the notes given, one per line
+fn report(error: &SoundError, args: &Args) {
+    if error.location().is_none() {
+        eprintln!("❌ Error: {}", error);
+        return;
+    }
+    let file = match args.notes.as_slice() {
+        [path] if Path::new(path).is_file() => fs::read_to_string(path)
+            .ok()
+            .map(|text| (path.clone(), text)),
+        _ => None,
+    };
+    let (origin, source) = file.unwrap_or_else(|| ("notes".to_string(), args.notes.join("\n")));
+
+    eprint!("{}", error.render(&origin, &source));
+    let count = error.count();
+    eprintln!(
+        "❌ {} error{} in {}",
+        count,
+        if count == 1 { "" } else { "s" },
+        origin
+    );
+}
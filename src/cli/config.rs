@@ -1 +1,406 @@
+//! Configuration files with default command line settings
+//!
+//! Settings are read from `~/.config/sound/config.toml` (or
+//! `$XDG_CONFIG_HOME/sound/config.toml`) and then `./sound.toml`. The project
+//! file overrides the user file, and flags given on the command line override
+//! both.
+//!
+//! ```toml
+//! scale = "dorian"
+//! key = "D"
+//! bpm = 96
+//! duration = "eighth"
+//! meter = "3/4"
+//! gate = 0.9
+//! wave = "fm:epiano"
+//! reverb = true
+//!
+//! [audio]
+//! device = "USB Audio"
+//! buffer_size = 256
+//!
+//! [jam]
+//! layout = "azerty"
+//!
+//! [scales.mystic]
+//! intervals = [0, 2, 3, 7, 8]
+//! aliases = ["myst"]
+//! ```
 
+use super::args::Args;
+use crate::music::scale::Scale;
+use clap::{parser::ValueSource, ArgMatches};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Defaults for every `Args` field, each unset unless a config file gives it
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    pub notes: Option<Vec<String>>,
+    pub scale: Option<String>,
+    pub scale_intervals: Option<String>,
+    pub wave: Option<String>,
+    pub instrument: Option<String>,
+    pub key: Option<String>,
+    pub bpm: Option<u32>,
+    pub r#loop: Option<bool>,
+    pub duration: Option<String>,
+    pub meter: Option<String>,
+    pub gate: Option<f32>,
+    pub delay: Option<bool>,
+    pub reverb: Option<bool>,
+    pub chorus: Option<bool>,
+    pub output: Option<String>,
+    pub device: Option<String>,
+    pub buffer_size: Option<u32>,
+    pub layout: Option<String>,
+    /// Custom scales as (id, aliases, scale), added to the built-in ones
+    pub scales: Vec<(String, Vec<String>, Scale)>,
+}
+
+impl Config {
+    /// Read the user config and then the project config, where present
+    pub fn load() -> Result<Self, String> {
+        let mut config = Config::default();
+        for path in config_paths() {
+            if path.is_file() {
+                config = Self::load_file(&path)?.or(config);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn load_file(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|error| format!("Cannot read {}: {}", path.display(), error))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Self::from_toml(&text, dir).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    /// Parse a config document; relative instrument paths are taken from `dir`
+    pub fn from_toml(text: &str, dir: &Path) -> Result<Self, String> {
+        let file: ConfigFile = toml::from_str(text).map_err(|error| error.to_string())?;
+        let audio = file.audio.unwrap_or_default();
+        let jam = file.jam.unwrap_or_default();
+
+        let mut config = Config {
+            notes: file.notes.map(Strings::into_vec),
+            scale: file.scale,
+            scale_intervals: file.scale_intervals.map(Intervals::into_string),
+            wave: file.wave,
+            instrument: file
+                .instrument
+                .map(|path| dir.join(path).to_string_lossy().into_owned()),
+            key: file.key,
+            bpm: file.bpm,
+            r#loop: file.r#loop,
+            duration: file.duration,
+            meter: file.meter,
+            gate: file.gate,
+            delay: file.delay,
+            reverb: file.reverb,
+            chorus: file.chorus,
+            output: file.output,
+            device: audio.device,
+            buffer_size: audio.buffer_size,
+            // A layout is a name or its rows of keys
+            layout: jam.layout.map(|rows| rows.into_vec().join(" ")),
+            scales: Vec::new(),
+        };
+
+        for (id, scale) in file.scales {
+            let intervals = scale
+                .intervals
+                .ok_or_else(|| format!("scales.{} needs intervals", id))?
+                .into_string();
+            let display = scale.name.as_deref().unwrap_or(&id);
+            let parsed = Scale::parse(display, &intervals)?;
+            let aliases = scale.aliases.map(Strings::into_vec).unwrap_or_default();
+            config.scales.push((id, aliases, parsed));
+        }
+
+        Ok(config)
+    }
+
+    /// Settings from `self`, falling back to `other` where `self` has none
+    pub fn or(self, other: Config) -> Config {
+        Config {
+            notes: self.notes.or(other.notes),
+            scale: self.scale.or(other.scale),
+            scale_intervals: self.scale_intervals.or(other.scale_intervals),
+            wave: self.wave.or(other.wave),
+            instrument: self.instrument.or(other.instrument),
+            key: self.key.or(other.key),
+            bpm: self.bpm.or(other.bpm),
+            r#loop: self.r#loop.or(other.r#loop),
+            duration: self.duration.or(other.duration),
+            meter: self.meter.or(other.meter),
+            gate: self.gate.or(other.gate),
+            delay: self.delay.or(other.delay),
+            reverb: self.reverb.or(other.reverb),
+            chorus: self.chorus.or(other.chorus),
+            output: self.output.or(other.output),
+            device: self.device.or(other.device),
+            buffer_size: self.buffer_size.or(other.buffer_size),
+            layout: self.layout.or(other.layout),
+            // Both sets of scales are kept, with ours added last so they win
+            scales: other.scales.into_iter().chain(self.scales).collect(),
+        }
+    }
+
+    /// Fill in every argument that was not given on the command line
+    pub fn apply(&self, args: &mut Args, matches: &ArgMatches) {
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+
+        if unset("notes") {
+            if let Some(notes) = &self.notes {
+                args.notes = notes.clone();
+            }
+        }
+        if unset("scale") {
+            if let Some(scale) = &self.scale {
+                args.scale = scale.clone();
+            }
+        }
+        if unset("scale_intervals") && self.scale_intervals.is_some() {
+            args.scale_intervals = self.scale_intervals.clone();
+        }
+        if unset("wave") {
+            if let Some(wave) = &self.wave {
+                args.wave = wave.clone();
+            }
+        }
+        if unset("instrument") && self.instrument.is_some() {
+            args.instrument = self.instrument.clone();
+        }
+        if unset("key") {
+            if let Some(key) = &self.key {
+                args.key = key.clone();
+            }
+        }
+        if unset("bpm") {
+            if let Some(bpm) = self.bpm {
+                args.bpm = bpm;
+            }
+        }
+        if unset("loop") {
+            if let Some(r#loop) = self.r#loop {
+                args.r#loop = r#loop;
+            }
+        }
+        if unset("duration") {
+            if let Some(duration) = &self.duration {
+                args.duration = duration.clone();
+            }
+        }
+        if unset("meter") {
+            if let Some(meter) = &self.meter {
+                args.meter = meter.clone();
+            }
+        }
+        if unset("gate") {
+            if let Some(gate) = self.gate {
+                args.gate = gate;
+            }
+        }
+        if unset("delay") {
+            if let Some(delay) = self.delay {
+                args.delay = delay;
+            }
+        }
+        if unset("reverb") {
+            if let Some(reverb) = self.reverb {
+                args.reverb = reverb;
+            }
+        }
+        if unset("chorus") {
+            if let Some(chorus) = self.chorus {
+                args.chorus = chorus;
+            }
+        }
+        if unset("output") && self.output.is_some() {
+            args.output = self.output.clone();
+        }
+        if unset("device") && self.device.is_some() {
+            args.device = self.device.clone();
+        }
+        if unset("buffer_size") && self.buffer_size.is_some() {
+            args.buffer_size = self.buffer_size;
+        }
+        if unset("layout") && self.layout.is_some() {
+            args.layout = self.layout.clone();
+        }
+        for (id, aliases, scale) in &self.scales {
+            args.scales.add(id, aliases, scale.clone());
+        }
+    }
+}
+
+/// Config files in increasing order of precedence
+fn config_paths() -> Vec<PathBuf> {
+    let user_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+
+    let mut paths = Vec::new();
+    if let Some(dir) = user_dir {
+        paths.push(dir.join("sound").join("config.toml"));
+    }
+    paths.push(PathBuf::from("sound.toml"));
+    paths
+}
+
+/// A config file as written, before it is merged with the others
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    notes: Option<Strings>,
+    scale: Option<String>,
+    scale_intervals: Option<Intervals>,
+    wave: Option<String>,
+    instrument: Option<String>,
+    key: Option<String>,
+    bpm: Option<u32>,
+    r#loop: Option<bool>,
+    duration: Option<String>,
+    meter: Option<String>,
+    gate: Option<f32>,
+    delay: Option<bool>,
+    reverb: Option<bool>,
+    chorus: Option<bool>,
+    output: Option<String>,
+    audio: Option<AudioSection>,
+    jam: Option<JamSection>,
+    #[serde(default)]
+    scales: BTreeMap<String, ScaleSection>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AudioSection {
+    device: Option<String>,
+    buffer_size: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JamSection {
+    layout: Option<Strings>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScaleSection {
+    intervals: Option<Intervals>,
+    aliases: Option<Strings>,
+    name: Option<String>,
+}
+
+/// A string, or an array of strings
+#[derive(Debug, Deserialize)]
+#[serde(untagged, expecting = "a string or an array of strings")]
+enum Strings {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Strings {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            Strings::One(s) => vec![s],
+            Strings::Many(items) => items,
+        }
+    }
+}
+
+/// Scale intervals as an array of integers or a comma-separated string
+#[derive(Debug, Deserialize)]
+#[serde(
+    untagged,
+    expecting = "an array of integers or a comma-separated string"
+)]
+enum Intervals {
+    Text(String),
+    List(Vec<i64>),
+}
+
+impl Intervals {
+    fn into_string(self) -> String {
+        match self {
+            Intervals::Text(s) => s,
+            Intervals::List(numbers) => numbers
+                .iter()
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_every_section() {
+        let text = r#"
+            notes = "1 2 3"
+            scale_intervals = [0, 2, 4]
+            bpm = 96
+            instrument = "bass.toml"
+
+            [audio]
+            buffer_size = 256
+
+            [jam]
+            layout = ["qwerty", "asdf"]
+
+            [scales.mystic]
+            intervals = "0,2,3,7,8"
+            aliases = ["myst"]
+        "#;
+        let config = Config::from_toml(text, Path::new("presets")).unwrap();
+        assert_eq!(config.notes, Some(vec!["1 2 3".to_string()]));
+        assert_eq!(config.scale_intervals.as_deref(), Some("0,2,4"));
+        assert_eq!(config.bpm, Some(96));
+        assert_eq!(
+            config.instrument,
+            Some(
+                Path::new("presets")
+                    .join("bass.toml")
+                    .to_string_lossy()
+                    .into()
+            )
+        );
+        assert_eq!(config.buffer_size, Some(256));
+        assert_eq!(config.layout.as_deref(), Some("qwerty asdf"));
+        let (id, aliases, scale) = &config.scales[0];
+        assert_eq!(id, "mystic");
+        assert_eq!(aliases, &["myst"]);
+        assert_eq!(scale.intervals, vec![0, 2, 3, 7, 8, 12]);
+    }
+
+    #[test]
+    fn rejects_unknown_and_mistyped_settings() {
+        for text in [
+            "tempo = 120",
+            "bpm = -1",
+            "reverb = \"yes\"",
+            "[audio]\nvolume = 3",
+            "[scales.empty]\naliases = [\"e\"]",
+        ] {
+            assert!(Config::from_toml(text, Path::new("")).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn later_files_win() {
+        let user = Config::from_toml("bpm = 90\nkey = \"D\"", Path::new("")).unwrap();
+        let project = Config::from_toml("bpm = 140", Path::new("")).unwrap();
+        let config = project.or(user);
+        assert_eq!(config.bpm, Some(140));
+        assert_eq!(config.key.as_deref(), Some("D"));
+    }
+}
@@ -0,0 +1,89 @@
+use super::args::Args;
+use super::args::FmtArgs;
+use super::play::{meter_from_args, scale_from_args};
+use crate::error::SoundError;
+use crate::music::abc::{format_abc, AbcTune};
+use crate::music::score::{format_score, parse_score_in_meter};
+use crate::{format_aligned_notation, parse_key, parse_note_notation_in_meter, Key, NoteElement};
+use std::fs;
+use std::path::Path;
+
+/// Print notes or a score file in canonical notation or as ABC, or rewrite
+/// the file
+pub fn fmt(fmt_args: &FmtArgs) -> Result<(), SoundError> {
+    let args = &fmt_args.args;
+    let scale_len = scale_from_args(args)?.len();
+    let (meter, symbol_ticks) = meter_from_args(args)?;
+
+    match args.notes.as_slice() {
+        [path] if Path::new(path).is_file() => {
+            let text = fs::read_to_string(path).map_err(|error| SoundError::Io {
+                path: path.clone(),
+                message: format!("cannot read: {}", error),
+            })?;
+            let score = parse_score_in_meter(&text, scale_len, meter, symbol_ticks)?;
+            if fmt_args.abc {
+                let voices = score
+                    .tracks()
+                    .into_iter()
+                    .map(|track| track.note_elements)
+                    .collect();
+                let title = Path::new(path)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned());
+                print!("{}", abc(args, title, voices)?);
+                return Ok(());
+            }
+            let formatted = format_score(&score, scale_len, fmt_args.bar)?;
+            if fmt_args.write {
+                fs::write(path, formatted).map_err(|error| SoundError::Io {
+                    path: path.clone(),
+                    message: format!("cannot write: {}", error),
+                })?;
+                println!("✅ Formatted {}", path);
+            } else {
+                print!("{}", formatted);
+            }
+        }
+        _ => {
+            if fmt_args.write {
+                return Err(SoundError::Invalid(
+                    "--write needs a score file to rewrite".to_string(),
+                ));
+            }
+            let elements =
+                parse_note_notation_in_meter(&args.notes, scale_len, meter, symbol_ticks)?;
+            if fmt_args.abc {
+                print!("{}", abc(args, None, vec![elements])?);
+                return Ok(());
+            }
+            for line in format_aligned_notation(&[elements], scale_len, fmt_args.bar)? {
+                println!("{}", line);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The voices as an ABC tune in the key, scale, meter and tempo of the
+/// arguments
+fn abc(
+    args: &Args,
+    title: Option<String>,
+    voices: Vec<Vec<NoteElement>>,
+) -> Result<String, SoundError> {
+    let scale = scale_from_args(args)?;
+    let (meter, symbol_ticks) = meter_from_args(args)?;
+    let (root, octave) = parse_key(&args.key)?;
+    format_abc(&AbcTune {
+        title,
+        key: Key::new(root.note(), octave),
+        scale_name: scale.name,
+        scale_intervals: scale.intervals,
+        meter,
+        symbol_ticks,
+        bpm: Some(args.bpm),
+        voices,
+    })
+}
@@ -1,31 +1,43 @@
 use super::args::Args;
-use crate::audio::pulse::Pulse;
-use crate::audio::square::Square;
-use crate::audio::triangle::Triangle;
+use super::play::{effects_chain, instrument_from_args, output_device, scale_from_args};
+use crate::audio::bus::MasterBus;
+use crate::music::duration::duration;
 use crate::music::key::Key;
 use crate::music::note::{MusicNote, Note};
-use crate::music::util::get_scale_by_name;
+use crate::music::spelling::KeySpelling;
+use crate::parse_key;
 use crossterm::event::{
     read, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
     PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
 };
 
-use crate::audio::wave::{get_wave_type, Wave};
+use crate::audio::{instrument::Instrument, SAMPLE_RATE};
 use crossterm::execute;
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
 use rodio::{
     cpal::{traits::*, BufferSize, SupportedBufferSize},
-    source::{LimitSettings, SineWave},
-    OutputStream, OutputStreamBuilder, Sink, Source,
+    OutputStream, OutputStreamBuilder, Sink,
 };
-use std::collections::HashMap;
-use std::time::Duration;
+use std::collections::{hash_map::Entry, HashMap};
+
+/// Chords play quieter than single notes, at the level of the old pulse chords
+const CHORD_LEVEL: f32 = 0.1;
 
 pub fn jam(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
-    let mut key = Key::new(Note::C, 4);
-    let (scale_intervals, _scale_name) = get_scale_by_name(&args.scale)?;
+    let (root, octave) = parse_key(&args.key)?;
+    let mut key = Key::new(root.note(), octave);
+    let scale = scale_from_args(args)?;
+    let scale_intervals = &scale.intervals;
+    let instrument = instrument_from_args(args)?;
+    let key_rows = key_rows(args.layout.as_deref())?;
+
+    let stream_handle = build_stream_handle(args)?;
+    // Held notes are separate voices, so the instrument's effects go on the bus
+    let effects = effects_chain(args).or(instrument.effects);
+    let bus = MasterBus::connect(&stream_handle, &effects);
 
-    let stream_handle = build_stream_handle()?;
+    println!("Chord keys:");
+    print_chord_progression(&key, scale_intervals, &args.scale);
 
     enable_raw_mode()?;
     let mut stdout = std::io::stdout();
@@ -48,59 +60,53 @@ pub fn jam(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
                         break;
                     }
 
-                    // Change octave
+                    // Change octave, staying put at the ends of the range
                     if key_event.code == KeyCode::Up {
-                        key.octave += 1;
+                        key = key.shift_octaves(1).unwrap_or(key);
                     }
                     if key_event.code == KeyCode::Down {
-                        key.octave -= 1;
+                        key = key.shift_octaves(-1).unwrap_or(key);
                     }
 
                     // Play note
                     if let KeyCode::Char(c) = key_event.code {
                         let (octave_offset, scale_index_opt) =
-                            get_key_mapping(c, (scale_intervals.len() - 1).min(7));
+                            get_key_mapping(c, scale.len().min(7), &key_rows);
 
                         if let Some(scale_index) = scale_index_opt {
-                            if !active_keys.contains_key(&key_id) {
-                                let note_key = Key::new(key.root, key.octave + octave_offset);
-                                let note = MusicNote::from_key_interval(
-                                    &note_key,
-                                    scale_intervals[scale_index],
-                                    Duration::from_secs(10),
-                                );
-
-                                let sink = Sink::connect_new(&stream_handle.mixer());
-                                let wave = Wave::infinite(
-                                    get_wave_type(&args.wave),
-                                    note.frequency(),
-                                    44100,
-                                );
-
-                                let settings = LimitSettings::default()
-                                    .with_threshold(-6.0) // -6 dBFS threshold
-                                    .with_attack(Duration::from_millis(5))
-                                    .with_release(Duration::from_millis(100));
-
-                                let limited = wave.limit(settings);
-
-                                sink.append(limited);
-                                active_keys.insert(key_id, sink);
+                            // Notes above the highest octave are not played
+                            let note =
+                                key.shift_octaves(octave_offset as i32)
+                                    .and_then(|note_key| {
+                                        MusicNote::from_key_interval(
+                                            &note_key,
+                                            scale_intervals[scale_index],
+                                            duration::whole_note(),
+                                        )
+                                    });
+                            if let (Entry::Vacant(entry), Ok(note)) =
+                                (active_keys.entry(key_id), note)
+                            {
+                                let sink = bus.sink();
+                                let wave = instrument.held_note(note.frequency(), SAMPLE_RATE);
+
+                                sink.append(bus.voice(instrument.voice(wave), 1.0));
+                                entry.insert(sink);
                             }
                         } else {
                             // Check for chord mapping - use relative major chords for minor scales
                             let chord_info_opt =
-                                get_chord_mapping(c, &key, &scale_intervals, &args.scale);
+                                get_chord_mapping(c, &key, scale_intervals, &args.scale);
 
                             if let Some((chord_intervals, chord_name)) = chord_info_opt {
                                 if !active_keys.contains_key(&key_id) {
                                     // Get the chord root key (relative major for minor scales)
-                                    let chord_root_key =
-                                        get_chord_root_key(&key, &scale_intervals, &args.scale);
+                                    let chord_root_key = get_chord_root_key(&key, scale_intervals);
 
                                     // Play the chord by creating multiple sinks
                                     let chord_sinks = play_chord(
-                                        &stream_handle,
+                                        &bus,
+                                        &instrument,
                                         &chord_root_key,
                                         &chord_intervals,
                                     );
@@ -155,15 +161,12 @@ pub fn jam(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn build_stream_handle() -> Result<OutputStream, Box<dyn std::error::Error>> {
-    let host = rodio::cpal::default_host();
-    let device = host
-        .default_output_device()
-        .ok_or("No default output device found")?;
+fn build_stream_handle(args: &Args) -> Result<OutputStream, Box<dyn std::error::Error>> {
+    let device = output_device(args)?;
 
     println!("Audio Device: {}", device.name()?);
 
-    let mut stream_handle = OutputStreamBuilder::from_default_device()?;
+    let mut stream_handle = OutputStreamBuilder::from_device(device.clone())?;
     // Get supported configurations
     let mut supported_configs = device.supported_output_configs()?;
     if let Some(config) = supported_configs.next() {
@@ -177,10 +180,15 @@ fn build_stream_handle() -> Result<OutputStream, Box<dyn std::error::Error>> {
         match config.buffer_size() {
             SupportedBufferSize::Range { min, max } => {
                 println!("Supported Buffer Size Range: {} - {} frames", min, max);
-                stream_handle = stream_handle.with_buffer_size(BufferSize::Fixed(*min));
+                // Smallest buffer for the lowest latency, unless one was configured
+                let frames = args.buffer_size.unwrap_or(*min);
+                stream_handle = stream_handle.with_buffer_size(BufferSize::Fixed(frames));
             }
             SupportedBufferSize::Unknown => {
                 println!("Buffer Size: Unknown");
+                if let Some(frames) = args.buffer_size {
+                    stream_handle = stream_handle.with_buffer_size(BufferSize::Fixed(frames));
+                }
             }
         }
     }
@@ -195,37 +203,44 @@ fn build_stream_handle() -> Result<OutputStream, Box<dyn std::error::Error>> {
     Ok(stream_handle)
 }
 
-fn get_key_mapping(c: char, scale_length: usize) -> (u8, Option<usize>) {
-    // Define the keyboard layout for each row
-    let number_keys = ['1', '2', '3', '4', '5', '6', '7', '8', '9', '0'];
-    let qwerty_keys = ['q', 'w', 'e', 'r', 't', 'y', 'u', 'i', 'o', 'p'];
-    let asdf_keys = ['a', 's', 'd', 'f', 'g', 'h', 'j', 'k', 'l', ';'];
-    let zxcv_keys = ['z', 'x', 'c', 'v', 'b', 'n', 'm', ',', '.', '/'];
-
-    let c_lower = c.to_ascii_lowercase();
-
-    // Check each row and return (octave_offset, scale_index) if within scale_length
-    if let Some(pos) = number_keys.iter().position(|&k| k == c_lower) {
-        if pos < scale_length {
-            return (0, Some(pos));
+/// Rows of note keys, each an octave above the one before, for a named
+/// layout or rows of keys separated by spaces
+fn key_rows(layout: Option<&str>) -> Result<Vec<Vec<char>>, String> {
+    let rows: Vec<&str> = match layout.map(|l| l.trim().to_lowercase()).as_deref() {
+        None | Some("qwerty") => vec!["1234567890", "qwertyuiop", "asdfghjkl;", "zxcvbnm,./"],
+        Some("azerty") => vec!["&é\"'(-è_çà", "azertyuiop", "qsdfghjklm", "wxcvbn,;:!"],
+        Some("qwertz") => vec!["1234567890", "qwertzuiop", "asdfghjklö", "yxcvbnm,.-"],
+        Some("dvorak") => vec!["1234567890", "',.pyfgcrl", "aoeuidhtns", ";qjkxbmwvz"],
+        Some(custom) => {
+            let rows: Vec<&str> = layout.unwrap_or_default().split_whitespace().collect();
+            if rows.len() < 2 {
+                return Err(format!(
+                    "Unknown layout '{}'. Try: qwerty, azerty, qwertz, dvorak, or rows of keys separated by spaces",
+                    custom
+                ));
+            }
+            if rows.len() > 4 {
+                return Err("A jam layout has at most four rows of keys".to_string());
+            }
+            rows
         }
-    }
+    };
 
-    if let Some(pos) = qwerty_keys.iter().position(|&k| k == c_lower) {
-        if pos < scale_length {
-            return (1, Some(pos));
-        }
-    }
+    Ok(rows
+        .iter()
+        .map(|row| row.chars().map(|c| c.to_ascii_lowercase()).collect())
+        .collect())
+}
 
-    if let Some(pos) = asdf_keys.iter().position(|&k| k == c_lower) {
-        if pos < scale_length {
-            return (2, Some(pos));
-        }
-    }
+fn get_key_mapping(c: char, scale_length: usize, rows: &[Vec<char>]) -> (u8, Option<usize>) {
+    let c_lower = c.to_ascii_lowercase();
 
-    if let Some(pos) = zxcv_keys.iter().position(|&k| k == c_lower) {
-        if pos < scale_length {
-            return (3, Some(pos));
+    // Check each row and return (octave_offset, scale_index) if within scale_length
+    for (octave_offset, row) in rows.iter().enumerate() {
+        if let Some(pos) = row.iter().position(|&k| k == c_lower) {
+            if pos < scale_length {
+                return (octave_offset as u8, Some(pos));
+            }
         }
     }
 
@@ -252,10 +267,10 @@ fn get_chord_mapping(
     None
 }
 
-fn get_chord_root_key(base_key: &Key, _scale_intervals: &[i32], scale_name: &str) -> Key {
+fn get_chord_root_key(base_key: &Key, scale_intervals: &[i32]) -> Key {
     // For minor scales, find the relative major (3 semitones up)
-    if is_minor_scale(scale_name) {
-        let relative_major_root = base_key.note_at_interval(3).0;
+    if is_minor_scale(scale_intervals) {
+        let relative_major_root = Note::from_semitone(base_key.root.to_semitone() + 3);
         Key::new(relative_major_root, base_key.octave)
     } else {
         // For major and other scales, use the original key
@@ -263,15 +278,15 @@ fn get_chord_root_key(base_key: &Key, _scale_intervals: &[i32], scale_name: &str
     }
 }
 
-fn is_minor_scale(scale_name: &str) -> bool {
-    match scale_name.to_lowercase().as_str() {
-        "minor" | "natural_minor" | "harmonic_minor" | "melodic_minor" | "harmonic" | "dorian"
-        | "phrygian" | "locrian" | "minor_pentatonic" | "minor_penta" | "blues" => true,
-        _ => false,
-    }
+/// A scale is minor when it has a minor third and no major third, which
+/// also covers custom scales
+fn is_minor_scale(scale_intervals: &[i32]) -> bool {
+    use crate::music::interval::{MAJOR_THIRD, MINOR_THIRD};
+
+    scale_intervals.contains(&MINOR_THIRD) && !scale_intervals.contains(&MAJOR_THIRD)
 }
 
-fn build_diatonic_triad(scale_intervals: &[i32], degree: usize) -> (Vec<i32>, String) {
+pub fn build_diatonic_triad(scale_intervals: &[i32], degree: usize) -> (Vec<i32>, String) {
     let scale_len = scale_intervals.len() - 1; // Exclude octave
 
     // Build triad using scale degrees (1st, 3rd, 5th of the scale)
@@ -291,35 +306,50 @@ fn build_diatonic_triad(scale_intervals: &[i32], degree: usize) -> (Vec<i32>, St
         fifth_interval += 12;
     }
 
-    let (chord_intervals, chord_name) = match (third_interval, fifth_interval) {
-        (3, 7) => (vec![root, third, fifth], format!("{}m", degree + 1)), // Minor
-        (4, 7) => (vec![root, third, fifth], format!("{}", degree + 1)),  // Major
-        (3, 6) => (vec![root, third, fifth], format!("{}°", degree + 1)), // Diminished
-        (4, 8) => (vec![root, third, fifth], format!("{}+", degree + 1)), // Augmented
-        _ => (vec![root, third, fifth], format!("{}?", degree + 1)),      // Unknown
-    };
+    let chord_name = format!(
+        "{}{}",
+        degree + 1,
+        triad_quality(third_interval, fifth_interval)
+    );
 
-    (chord_intervals, chord_name)
+    (vec![root, third, fifth], chord_name)
 }
 
-fn print_chord_progression(base_key: &Key, scale_intervals: &[i32], scale_name: &str) {
+/// Chord symbol suffix for a triad, from the semitones between its root and
+/// its third and fifth
+pub fn triad_quality(third_interval: i32, fifth_interval: i32) -> &'static str {
+    match (third_interval, fifth_interval) {
+        (3, 7) => "m", // Minor
+        (4, 7) => "",  // Major
+        (3, 6) => "°", // Diminished
+        (4, 8) => "+", // Augmented
+        _ => "?",      // Unknown
+    }
+}
+
+/// Show which keys play which chords in jam mode, with common progressions
+pub fn print_chord_progression(base_key: &Key, scale_intervals: &[i32], scale_name: &str) {
     use crate::music::interval;
 
     let chord_keys = ['-', '=', '[', ']', ';', '\''];
     let max_chords = 6; // Always show 6 chords from major scale
 
     // For minor scales, show relative major chords
-    let chord_root_key = get_chord_root_key(base_key, scale_intervals, scale_name);
-    let chord_context = if is_minor_scale(scale_name) {
-        format!("from {} Major", format!("{:?}", chord_root_key.root))
+    let chord_root_key = get_chord_root_key(base_key, scale_intervals);
+    let chord_root = if is_minor_scale(scale_intervals) {
+        chord_root_key.root
     } else {
-        format!("from {} Major", format!("{:?}", base_key.root))
+        base_key.root
     };
+    let chord_context = format!(
+        "from {} Major",
+        KeySpelling::for_note(chord_root, &interval::MAJOR_SCALE).root()
+    );
 
     print!("  ");
-    for i in 0..max_chords {
+    for (i, chord_key) in chord_keys.iter().enumerate().take(max_chords) {
         let (_, chord_name) = build_diatonic_triad(&interval::MAJOR_SCALE, i);
-        print!("{} = {}   ", chord_keys[i], chord_name);
+        print!("{} = {}   ", chord_key, chord_name);
     }
     println!(" ({})", chord_context);
 
@@ -332,22 +362,24 @@ fn print_chord_progression(base_key: &Key, scale_intervals: &[i32], scale_name:
     }
 }
 
-fn play_chord(stream_handle: &rodio::OutputStream, base_key: &Key, intervals: &[i32]) -> Vec<Sink> {
+fn play_chord(
+    bus: &MasterBus,
+    instrument: &Instrument,
+    base_key: &Key,
+    intervals: &[i32],
+) -> Vec<Sink> {
     let mut sinks = Vec::new();
 
     for &interval in intervals {
-        let note = MusicNote::from_key_interval(base_key, interval, Duration::from_secs(10));
-
-        let sink = Sink::connect_new(&stream_handle.mixer());
-        let sine_wave = Pulse::new(note.frequency(), 41000, Duration::from_secs(10));
-
-        let settings = LimitSettings::default()
-            .with_threshold(-6.0)
-            .with_attack(Duration::from_millis(5))
-            .with_release(Duration::from_millis(100));
-
-        let limited = sine_wave.limit(settings);
-        sink.append(limited);
+        let Ok(note) = MusicNote::from_key_interval(base_key, interval, duration::whole_note())
+        else {
+            continue;
+        };
+
+        let sink = bus.sink();
+        // Chords ring for up to ten seconds, or until the key is released
+        let wave = instrument.note(&note, SAMPLE_RATE, SAMPLE_RATE as usize * 10);
+        sink.append(bus.voice(instrument.voice(wave), CHORD_LEVEL));
         sinks.push(sink);
     }
 
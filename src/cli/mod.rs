@@ -1,6 +1,13 @@
+pub mod analyze;
 pub mod args;
+pub mod chords;
+#[allow(clippy::module_inception)]
 pub mod cli;
 pub mod config;
+pub mod fmt;
 pub mod jam;
+pub mod parse;
 pub mod play;
 pub mod read;
+pub mod scales;
+pub mod transform;
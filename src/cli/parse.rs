@@ -0,0 +1,51 @@
+use super::args::ParseArgs;
+use super::play::{melody_config, meter_from_args, scale_from_args};
+use crate::error::SoundError;
+use crate::music::{abc::parse_abc, score::parse_score_in_meter};
+use std::fs;
+use std::path::Path;
+
+/// Print what the notes (or a score or ABC file) parse to, so other tools
+/// can see the structure without playing anything
+pub fn parse(parse_args: &ParseArgs) -> Result<(), SoundError> {
+    let args = &parse_args.args;
+    match args.notes.as_slice() {
+        [path] if Path::new(path).is_file() => {
+            let text = fs::read_to_string(path).map_err(|error| SoundError::Io {
+                path: path.clone(),
+                message: format!("cannot read: {}", error),
+            })?;
+            if path.to_lowercase().ends_with(".abc") {
+                return print(&parse_abc(&text)?, parse_args.json);
+            }
+            let (meter, symbol_ticks) = meter_from_args(args)?;
+            let score =
+                parse_score_in_meter(&text, scale_from_args(args)?.len(), meter, symbol_ticks)?;
+            print(&score, parse_args.json)
+        }
+        _ => print(&melody_config(args)?, parse_args.json),
+    }
+}
+
+#[cfg(feature = "serde")]
+fn print<T: serde::Serialize + std::fmt::Debug>(value: &T, json: bool) -> Result<(), SoundError> {
+    if json {
+        let text = serde_json::to_string_pretty(value)
+            .map_err(|error| SoundError::Invalid(format!("Cannot write JSON: {}", error)))?;
+        println!("{}", text);
+    } else {
+        println!("{:#?}", value);
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "serde"))]
+fn print<T: std::fmt::Debug>(value: &T, json: bool) -> Result<(), SoundError> {
+    if json {
+        return Err(SoundError::Invalid(
+            "JSON output needs the serde feature (cargo build --features serde)".to_string(),
+        ));
+    }
+    println!("{:#?}", value);
+    Ok(())
+}
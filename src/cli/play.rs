@@ -1,100 +1,220 @@
 use super::args::Args;
 use crate::{
-    get_scale_by_name, music::melody::Melody, parse_note_from_string, parse_note_notation, Key,
-    MelodyConfig, NoteElement,
+    audio::{
+        bus::MasterBus,
+        effects::{ChorusSettings, DelaySettings, EffectsChain, ReverbSettings},
+        instrument::Instrument,
+        render::write_wav,
+        stereo::pan,
+        wave::get_wave_type,
+        SAMPLE_RATE,
+    },
+    music::{duration::note_value, melody::Melody, scale::Scale},
+    parse_key, parse_note_notation_in_meter, Key, MelodyConfig, Meter, NoteElement, SoundError,
+    Ticks,
+};
+use rodio::{
+    cpal::{traits::*, BufferSize, Device},
+    OutputStream, OutputStreamBuilder, Source,
 };
-use rodio::{OutputStreamBuilder, Sink};
-use std::time::Duration;
-
-pub fn play(args: &Args) {
-    let stream_handle =
-        OutputStreamBuilder::open_default_stream().expect("Failed to open audio stream");
-    let sink = Sink::connect_new(&stream_handle.mixer());
 
+pub fn play(args: &Args) -> Result<(), SoundError> {
     // Create melody configuration from CLI arguments
-    match create_melody_config(&args) {
-        Ok(config) => {
-            println!("✅ Successfully parsed melody configuration:");
-            println!("  🎼 Scale: {}", config.scale_name);
-            println!("  🎹 Key: {:?}", config.key.root);
-            println!("  ⏱️  Duration: {} notes", config.base_duration);
-
-            let melody = Melody::new(config);
-
-            // Calculate sleep duration for one iteration
-            let total_elements = melody.note_elements.len();
-            let iteration_duration_ms =
-                total_elements as u64 * melody.base_note_duration.as_millis() as u64;
-
-            // Play the melody (looping if requested)
-            if melody.should_loop {
-                println!(
-                    "\n🔄 Playing your custom melody at {} BPM (looping - press Ctrl+C to stop)...",
-                    melody.bpm
-                );
-                loop {
-                    melody.play(&sink);
-                    std::thread::sleep(Duration::from_millis(iteration_duration_ms));
-                }
-            } else {
-                println!("\n🎶 Playing your custom melody at {} BPM...", melody.bpm);
-                melody.play(&sink);
-                std::thread::sleep(Duration::from_millis(iteration_duration_ms));
-            }
-
-            println!("✨ Custom melody complete!");
-        }
-        Err(error) => {
-            eprintln!("❌ Error: {}", error);
-            eprintln!("");
-            eprintln!("💡 Examples:");
-            eprintln!(
+    let config = melody_config(args)?;
+    println!("✅ Successfully parsed melody configuration:");
+    println!("  🎼 Scale: {}", config.scale_name);
+    if let Ok((root, octave)) = parse_key(&args.key) {
+        println!("  🎹 Key: {}{}", root, octave);
+    }
+    println!("  ⏱️  Duration: {} notes", config.base_duration);
+
+    play_melody(args, Melody::new(config))
+}
+
+/// Example commands, shown after a melody fails to parse
+pub fn print_examples() {
+    eprintln!();
+    eprintln!("💡 Examples:");
+    eprintln!(
             "   cargo run -- 12345 --scale major --bpm 120     # Notes 1,2,3,4,5 at 120 BPM (sixteenth notes)"
         );
-            eprintln!(
-                "   cargo run -- 1^234 --scale minor --key A --duration 4  # Quarter note duration"
-            );
-            eprintln!(
-            "   cargo run -- 1v23^45 --scale dorian --key D --duration 8  # Eighth note duration"
+    eprintln!("   cargo run -- 1^234 --scale minor --key A --duration 4  # Quarter note duration");
+    eprintln!(
+        "   cargo run -- 1v23^45 --scale dorian --key D --duration 8  # Eighth note duration"
+    );
+    eprintln!("   cargo run -- 12345 --duration 1 --bpm 60 --loop  # Very slow with whole notes");
+    eprintln!();
+    eprintln!("Run 'cargo run -- --help' for more information.");
+}
+
+/// Play a melody live, or render it when --output is given
+pub fn play_melody(args: &Args, melody: Melody) -> Result<(), SoundError> {
+    for error in melody.errors() {
+        println!("⚠️  Warning: {}", error);
+    }
+    let effects = effects_chain(args);
+
+    if let Some(path) = &args.output {
+        let (bus, output) = MasterBus::offline(&effects);
+        bus.add(pan(melody.source(), 0.0), 1.0);
+        return render(path, output);
+    }
+
+    let stream_handle = open_stream(args)?;
+    let bus = MasterBus::connect(&stream_handle, &effects);
+    let sink = bus.sink();
+
+    // Sleep for one iteration
+    let iteration = melody.length();
+
+    // Play the melody (looping if requested)
+    if melody.should_loop {
+        println!(
+            "\n🔄 Playing your custom melody at {} BPM (looping - press Ctrl+C to stop)...",
+            melody.bpm
         );
-            eprintln!(
-                "   cargo run -- 12345 --duration 1 --bpm 60 --loop  # Very slow with whole notes"
-            );
-            eprintln!("");
-            eprintln!("Run 'cargo run -- --help' for more information.");
-            std::process::exit(1);
+        loop {
+            sink.append(bus.voice(pan(melody.source(), 0.0), 1.0));
+            std::thread::sleep(iteration);
         }
+    } else {
+        println!("\n🎶 Playing your custom melody at {} BPM...", melody.bpm);
+        sink.append(bus.voice(pan(melody.source(), 0.0), 1.0));
+        std::thread::sleep(iteration);
+        // Let delay repeats and reverb ring out
+        std::thread::sleep(effects.tail());
     }
+
+    println!("✨ Custom melody complete!");
+    Ok(())
 }
 
-pub fn create_melody_config(args: &Args) -> Result<MelodyConfig, String> {
-    println!(
-        "CLI args: scale={}, key={}, notes={:?}, bpm={}, loop={}, duration={}",
-        args.scale, args.key, args.notes, args.bpm, args.r#loop, args.duration
-    );
+/// Build the effects chain requested on the command line
+pub fn effects_chain(args: &Args) -> EffectsChain {
+    EffectsChain {
+        chorus: args.chorus.then(ChorusSettings::default),
+        delay: args.delay.then(|| DelaySettings::synced(args.bpm, 0.75)),
+        reverb: args.reverb.then(ReverbSettings::default),
+    }
+}
+
+/// The instrument preset given with --instrument, or a bare --wave oscillator
+pub fn instrument_from_args(args: &Args) -> Result<Instrument, SoundError> {
+    Ok(match &args.instrument {
+        Some(path) => Instrument::load(path, args.bpm)?,
+        None => Instrument::from_wave(get_wave_type(&args.wave)?),
+    })
+}
+
+/// The scale given with --scale-intervals, or --scale looked up by name
+pub fn scale_from_args(args: &Args) -> Result<Scale, SoundError> {
+    match &args.scale_intervals {
+        Some(intervals) => Scale::parse("Custom", intervals),
+        None => args.scales.get(&args.scale).cloned(),
+    }
+}
+
+/// The meter given with --meter and the length of one symbol from
+/// --duration, which bar lines are checked against
+pub fn meter_from_args(args: &Args) -> Result<(Meter, Ticks), SoundError> {
+    let symbol_ticks = note_value(&args.duration).ok_or_else(|| {
+        SoundError::Invalid(
+            "Duration must be one of: whole/1, half/2, quarter/4, eighth/8, sixteenth/16"
+                .to_string(),
+        )
+    })?;
+    Ok((Meter::parse(&args.meter)?, symbol_ticks))
+}
+
+/// The output device named with --device, or the system default
+pub fn output_device(args: &Args) -> Result<Device, SoundError> {
+    let host = rodio::cpal::default_host();
+    let Some(wanted) = &args.device else {
+        return host
+            .default_output_device()
+            .ok_or_else(|| SoundError::Audio("No default output device found".to_string()));
+    };
 
+    let devices: Vec<Device> = host
+        .output_devices()
+        .map_err(|error| SoundError::Audio(error.to_string()))?
+        .collect();
+    let names: Vec<String> = devices
+        .iter()
+        .map(|device| device.name().unwrap_or_default())
+        .collect();
+    let wanted_lower = wanted.to_lowercase();
+    let index = names
+        .iter()
+        .position(|name| name == wanted)
+        .or_else(|| {
+            names
+                .iter()
+                .position(|name| name.to_lowercase().contains(&wanted_lower))
+        })
+        .ok_or_else(|| {
+            SoundError::Audio(format!(
+                "No output device matching '{}'. Available: {}",
+                wanted,
+                names.join(", ")
+            ))
+        })?;
+    Ok(devices
+        .into_iter()
+        .nth(index)
+        .expect("index of a listed device"))
+}
+
+/// Open the output stream on the configured device and buffer size
+pub fn open_stream(args: &Args) -> Result<OutputStream, SoundError> {
+    let failed =
+        |e: rodio::StreamError| SoundError::Audio(format!("Failed to open audio stream: {}", e));
+    let mut builder = OutputStreamBuilder::from_device(output_device(args)?).map_err(failed)?;
+    if let Some(frames) = args.buffer_size {
+        builder = builder.with_buffer_size(BufferSize::Fixed(frames));
+    }
+    builder.open_stream().map_err(failed)
+}
+
+/// Write a finite source to a WAV file, reporting the outcome
+pub fn render<S: Source>(path: &str, source: S) -> Result<(), SoundError> {
+    write_wav(path, source).map_err(|error| SoundError::Io {
+        path: path.to_string(),
+        message: format!("cannot write: {}", error),
+    })?;
+    println!("💾 Rendered to {}", path);
+    Ok(())
+}
+
+/// The melody config for the arguments
+pub fn melody_config(args: &Args) -> Result<MelodyConfig, SoundError> {
     // Validate BPM range
     if args.bpm == 0 || args.bpm > 500 {
-        return Err("BPM must be between 1 and 500".to_string());
+        return Err(SoundError::Invalid(
+            "BPM must be between 1 and 500".to_string(),
+        ));
     }
 
     // Validate duration
-    match args.duration.to_lowercase().as_str() {
-        "whole" | "1" | "half" | "2" | "quarter" | "4" | "eighth" | "8" | "sixteenth" | "16" => {}
-        _ => {
-            return Err(
-                "Duration must be one of: whole/1, half/2, quarter/4, eighth/8, sixteenth/16"
-                    .to_string(),
-            )
-        }
+    if note_value(&args.duration).is_none() {
+        return Err(SoundError::Invalid(
+            "Duration must be one of: whole/1, half/2, quarter/4, eighth/8, sixteenth/16"
+                .to_string(),
+        ));
+    }
+
+    if !(args.gate > 0.0 && args.gate <= 1.0) {
+        return Err(SoundError::Invalid(
+            "Gate must be above 0 and at most 1".to_string(),
+        ));
     }
 
     // Parse scale
-    let (scale_intervals, scale_name) = get_scale_by_name(&args.scale)?;
+    let scale = scale_from_args(args)?;
 
     // Parse key/root note
-    let note = parse_note_from_string(&args.key)?;
-    let key = Key::new(note, 4);
+    let (root, octave) = parse_key(&args.key)?;
+    let key = Key::new(root.note(), octave);
 
     // Parse note elements or use default
     let note_elements = if args.notes.is_empty() {
@@ -109,18 +229,21 @@ pub fn create_melody_config(args: &Args) -> Result<MelodyConfig, String> {
             NoteElement::Note(8, 0),
         ]
     } else {
-        parse_note_notation(&args.notes)?
+        let (meter, symbol_ticks) = meter_from_args(args)?;
+        parse_note_notation_in_meter(&args.notes, scale.len(), meter, symbol_ticks)?
     };
 
     let config = MelodyConfig {
-        scale_name,
-        scale_intervals,
+        scale_name: scale.name,
+        scale_intervals: scale.intervals,
         note_elements,
-        sample_rate: 44100,
+        sample_rate: SAMPLE_RATE,
+        instrument: instrument_from_args(args)?,
         key,
         bpm: args.bpm,
         should_loop: args.r#loop,
         base_duration: args.duration.clone(),
+        gate: args.gate,
     };
 
     Ok(config)
@@ -1,54 +1,141 @@
 use super::args::Args;
-use super::play::create_melody_config;
-use crate::music::melody::Melody;
-use rodio::{OutputStreamBuilder, Sink};
-use std::{
-    fs::File,
-    io::{self, BufRead},
-    path::Path,
+use super::play::{
+    effects_chain, melody_config, meter_from_args, open_stream, render, scale_from_args,
 };
+use crate::audio::{bus::MasterBus, instrument::Instrument, stereo::pan, wave::get_wave_type};
+use crate::error::SoundError;
+use crate::music::{abc::parse_abc, melody::Melody, score::parse_score_in_meter};
+use std::fs;
+use std::path::{Path, PathBuf};
 
-pub fn read(args: &Args) {
+/// A voice ready to play, with its stereo position and gain
+type Voice = (Melody, f32, f32);
+
+pub fn read(args: &Args) -> Result<(), SoundError> {
     println!("Reading melody from file: {:?}", args.notes);
-    // Construct a dynamic controller and mixer, stream_handle, and sink.
-    let stream_handle =
-        OutputStreamBuilder::open_default_stream().expect("Failed to open audio stream");
-    // let sink = Sink::connect_new(&stream_handle.mixer());
-
-    let mut melodies = Vec::new();
-    // File hosts.txt must exist in the current path
-    if let Ok(lines) = read_lines(args.notes.first().unwrap()) {
-        // Consumes the iterator, returns an (Optional) String
-        for mut line in lines.map_while(Result::ok) {
-            let mut a = args.clone();
-            line.retain(|c| !c.is_whitespace());
-            a.notes = vec![line];
-            let config = create_melody_config(&a).unwrap();
-            let melody = Melody::new(config);
-            melodies.push(melody);
-        }
 
-        let mut sinks = Vec::new();
-        for melody in &melodies {
-            let sink = Sink::connect_new(&stream_handle.mixer()); // << new “channel”
-                                                                  // sink.pause();
-            melody.play(&sink);
-            sinks.push(sink);
+    let path = args
+        .notes
+        .first()
+        .ok_or_else(|| SoundError::Invalid("No file given".to_string()))?;
+    let text = fs::read_to_string(path).map_err(|error| SoundError::Io {
+        path: path.clone(),
+        message: format!("cannot read: {}", error),
+    })?;
+    let is_abc = Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("abc"));
+    if is_abc {
+        return read_abc(&text, args);
+    }
+
+    let (meter, symbol_ticks) = meter_from_args(args)?;
+    let score = parse_score_in_meter(&text, scale_from_args(args)?.len(), meter, symbol_ticks)?;
+
+    // The file name is not notation, so build the config without it
+    let mut a = args.clone();
+    a.notes = Vec::new();
+    let base_config = melody_config(&a)?;
+    let score_dir = Path::new(path).parent().unwrap_or(Path::new("."));
+
+    let mut voices = Vec::new();
+    for track in score.tracks() {
+        let mut config = base_config.clone();
+        config.instrument = match (&track.instrument, &track.wave) {
+            (Some(name), _) => Instrument::load(find_instrument(name, score_dir), args.bpm)?,
+            (None, Some(wave)) => Instrument::from_wave(get_wave_type(wave)?),
+            (None, None) => base_config.instrument.clone(),
+        };
+        config.note_elements = track.note_elements;
+        config.gate = track.gate.unwrap_or(base_config.gate);
+        let melody = Melody::new(config);
+        for error in melody.errors() {
+            println!("⚠️  Warning: {}", error);
         }
+        voices.push((melody, track.pan, track.gain));
+    }
+    play_voices(args, &voices)
+}
+
+/// Play an ABC tune, in the key, meter and tempo it gives
+fn read_abc(text: &str, args: &Args) -> Result<(), SoundError> {
+    let tune = parse_abc(text)?;
+    if let Some(title) = &tune.title {
+        println!("🎻 {}", title);
+    }
+
+    let mut a = args.clone();
+    a.notes = Vec::new();
+    let mut config = melody_config(&a)?;
+    config.key = tune.key;
+    config.scale_name = tune.scale_name.clone();
+    config.scale_intervals = tune.scale_intervals.clone();
+    config.bpm = tune.bpm.unwrap_or(args.bpm);
+    config.note_elements = Vec::new();
 
-        for sink in &sinks {
-            sink.sleep_until_end();
+    let voices: Vec<Voice> = tune
+        .voices
+        .iter()
+        .map(|elements| {
+            let melody = Melody::new(config.clone()).add_elements(elements, tune.symbol_ticks);
+            for error in melody.errors() {
+                println!("⚠️  Warning: {}", error);
+            }
+            (melody, 0.0, 1.0)
+        })
+        .collect();
+    play_voices(args, &voices)
+}
+
+/// Mix the voices and play them, or render them with --output
+fn play_voices(args: &Args, voices: &[Voice]) -> Result<(), SoundError> {
+    let effects = effects_chain(args);
+
+    if let Some(path) = &args.output {
+        let (bus, output) = MasterBus::offline(&effects);
+        for (melody, position, gain) in voices {
+            bus.add(pan(melody.source(), *position), *gain);
         }
-        // std::thread::sleep(Duration::from_millis(iteration_duration_ms));
+        return render(path, output);
+    }
+
+    let stream_handle = open_stream(args)?;
+    let bus = MasterBus::connect(&stream_handle, &effects);
+
+    let mut sinks = Vec::new();
+    for (melody, position, gain) in voices {
+        let sink = bus.sink(); // << new “channel”
+        sink.append(bus.voice(pan(melody.source(), *position), *gain));
+        sinks.push(sink);
+    }
+
+    for sink in &sinks {
+        sink.sleep_until_end();
     }
+    std::thread::sleep(effects.tail() + max_instrument_tail(voices));
+    Ok(())
+}
+
+/// Look for `<name>.toml` next to the score, then in an `instruments` folder
+/// beside it or in the current directory
+fn find_instrument(name: &str, score_dir: &Path) -> PathBuf {
+    let file = format!("{}.toml", name);
+    let candidates = [
+        score_dir.join(&file),
+        score_dir.join("instruments").join(&file),
+        Path::new("instruments").join(&file),
+    ];
+    candidates
+        .iter()
+        .find(|candidate| candidate.is_file())
+        .cloned()
+        .unwrap_or_else(|| candidates[0].clone())
 }
 
-// The output is wrapped in a Result to allow matching on errors.
-// Returns an Iterator to the Reader of the lines of the file.
-fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
-where
-    P: AsRef<Path>,
-{
-    let file = File::open(filename)?;
-    Ok(io::BufReader::new(file).lines())
+fn max_instrument_tail(voices: &[Voice]) -> std::time::Duration {
+    voices
+        .iter()
+        .map(|(melody, _, _)| melody.instrument.effects.tail())
+        .max()
+        .unwrap_or_default()
 }
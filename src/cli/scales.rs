@@ -0,0 +1,56 @@
+use super::args::ScalesArgs;
+use super::play::{melody_config, play_melody};
+use crate::error::SoundError;
+use crate::music::melody::{Melody, NoteElement};
+use crate::music::spelling::KeySpelling;
+use crate::parse_key;
+
+pub fn scales(scales_args: &ScalesArgs) -> Result<(), SoundError> {
+    let args = &scales_args.args;
+    let (root, _) = parse_key(&args.key)?;
+
+    if let Some(name) = &scales_args.play {
+        let mut a = args.clone();
+        a.scale = name.clone();
+        a.scale_intervals = None;
+        let mut config = melody_config(&a)?;
+
+        // Up to the octave and back down
+        let top = config.scale_intervals.len();
+        config.note_elements = (1..=top)
+            .chain((1..top).rev())
+            .map(|position| NoteElement::Note(position, 0))
+            .collect();
+
+        println!("🎼 {} {}", root, config.scale_name);
+        return play_melody(&a, Melody::new(config));
+    }
+
+    println!("🎼 Scales (notes in {}):", root);
+    for entry in args.scales.iter() {
+        let spelling = KeySpelling::new(root, &entry.scale.intervals);
+        let intervals: Vec<String> = entry.scale.intervals[..entry.scale.len()]
+            .iter()
+            .map(|interval| interval.to_string())
+            .collect();
+        let notes: Vec<String> = entry.scale.intervals[..entry.scale.len()]
+            .iter()
+            .map(|&interval| spelling.name(interval).to_string())
+            .collect();
+
+        println!(
+            "  {:<18} {:<20} {:<28} {}",
+            entry.id,
+            entry.scale.name,
+            intervals.join(" "),
+            notes.join(" ")
+        );
+        if !entry.aliases.is_empty() {
+            println!("  {:<18} also: {}", "", entry.aliases.join(", "));
+        }
+    }
+    println!();
+    println!("💡 Audition one with: sound scales --play dorian --key D");
+
+    Ok(())
+}
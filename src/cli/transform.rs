@@ -0,0 +1,73 @@
+use super::args::TransformArgs;
+use super::play::{meter_from_args, scale_from_args};
+use crate::error::SoundError;
+use crate::music::transform::{
+    change_mode, invert, retrograde, rotate, shift, stretch, to_elements, to_events,
+};
+use crate::{format_note_notation, parse_key, parse_note_notation_in_meter};
+
+pub fn transform(transform_args: &TransformArgs) -> Result<(), SoundError> {
+    let args = &transform_args.args;
+    let mut scale = scale_from_args(args)?;
+    let mut scale_id = args.scale.clone();
+    let mut key = args.key.clone();
+
+    let (meter, symbol_ticks) = meter_from_args(args)?;
+    let elements = parse_note_notation_in_meter(&args.notes, scale.len(), meter, symbol_ticks)?;
+    let mut events = to_events(&elements, scale.len())?;
+
+    if let Some(new_key) = &transform_args.transpose {
+        parse_key(new_key)?;
+        key = new_key.clone();
+    }
+    if let Some(name) = &transform_args.mode {
+        let target = args.scales.get(name)?.clone();
+        events = change_mode(&events, &scale.intervals, &target.intervals);
+        scale = target;
+        scale_id = name.clone();
+    }
+    if let Some(steps) = transform_args.steps {
+        events = shift(&events, steps);
+    }
+    if transform_args.invert {
+        events = invert(&events);
+    }
+    if let Some(by) = transform_args.rotate {
+        events = rotate(&events, by);
+    }
+    if transform_args.retrograde {
+        events = retrograde(&events);
+    }
+    if let Some(factor) = transform_args.augment {
+        if factor == 0 {
+            return Err(SoundError::Invalid(
+                "Cannot augment by a factor of 0".to_string(),
+            ));
+        }
+        events = stretch(&events, factor, 1);
+    }
+    if let Some(factor) = transform_args.diminish {
+        if factor == 0 {
+            return Err(SoundError::Invalid(
+                "Cannot diminish by a factor of 0".to_string(),
+            ));
+        }
+        events = stretch(&events, 1, factor);
+    }
+
+    let notation = format_note_notation(&to_elements(&events, scale.len()), scale.len())?;
+    let scale_flag = match &args.scale_intervals {
+        Some(intervals) if transform_args.mode.is_none() => {
+            format!("--scale-intervals {}", intervals)
+        }
+        _ => format!("--scale {}", scale_id),
+    };
+
+    println!("{}", notation);
+    eprintln!();
+    eprintln!(
+        "🎶 Play it with: sound {} --key {} {}",
+        notation, key, scale_flag
+    );
+    Ok(())
+}
@@ -0,0 +1,190 @@
+//! Errors from parsing notation, building melodies and playing them
+
+use std::fmt;
+
+/// Everything that can go wrong in the library, so tools embedding it can
+/// tell a typo in notation from a missing audio device
+#[derive(Debug, Clone, PartialEq)]
+pub enum SoundError {
+    /// Notation that cannot be read, at a 1-based line and column and
+    /// spanning `length` characters
+    Notation {
+        line: usize,
+        column: usize,
+        length: usize,
+        message: String,
+    },
+    /// A problem with a line of a score file, such as an unknown directive,
+    /// at a 1-based line
+    Score { line: usize, message: String },
+    /// A scale name that is not in the registry
+    UnknownScale { name: String, known: Vec<String> },
+    /// Scale intervals that do not make a scale
+    InvalidScale(String),
+    /// A note or key name that cannot be read
+    InvalidNote(String),
+    /// A note, octave or position outside what can be played
+    OutOfRange(String),
+    /// A file that cannot be read or written
+    Io { path: String, message: String },
+    /// No audio device, or a stream that cannot be opened
+    Audio(String),
+    /// Any other invalid setting
+    Invalid(String),
+    /// Several mistakes found in one pass, in the order they occur
+    Multiple(Vec<SoundError>),
+}
+
+impl SoundError {
+    /// One error as itself, several as `Multiple`
+    pub fn from_errors(mut errors: Vec<SoundError>) -> Self {
+        if errors.len() == 1 {
+            errors.remove(0)
+        } else {
+            SoundError::Multiple(errors)
+        }
+    }
+
+    /// Line and column the error points at, if it comes from notation (the
+    /// first of several); score lines without a column give column 1
+    pub fn location(&self) -> Option<(usize, usize)> {
+        match self {
+            SoundError::Notation { line, column, .. } => Some((*line, *column)),
+            SoundError::Score { line, .. } => Some((*line, 1)),
+            SoundError::Multiple(errors) => errors.iter().find_map(SoundError::location),
+            _ => None,
+        }
+    }
+
+    /// Number of mistakes this error reports
+    pub fn count(&self) -> usize {
+        match self {
+            SoundError::Multiple(errors) => errors.iter().map(SoundError::count).sum(),
+            _ => 1,
+        }
+    }
+
+    /// Move an error in one line of notation to a line of a larger file,
+    /// whose text started `indent` characters in
+    pub fn at_line(self, line: usize, indent: usize) -> Self {
+        match self {
+            SoundError::Notation {
+                column,
+                length,
+                message,
+                ..
+            } => SoundError::Notation {
+                line,
+                column: column + indent,
+                length,
+                message,
+            },
+            SoundError::Score { message, .. } | SoundError::Invalid(message) => {
+                SoundError::Score { line, message }
+            }
+            SoundError::Multiple(errors) => SoundError::Multiple(
+                errors
+                    .into_iter()
+                    .map(|error| error.at_line(line, indent))
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    /// Show the error the way rustc does, quoting the line of `source` it
+    /// points at with a caret under the column. `origin` names the source,
+    /// such as a file path. Errors without a location get just a header.
+    pub fn render(&self, origin: &str, source: &str) -> String {
+        match self {
+            SoundError::Multiple(errors) => errors
+                .iter()
+                .map(|error| error.render(origin, source))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            SoundError::Notation {
+                line,
+                column,
+                length,
+                message,
+            } => snippet(origin, source, *line, *column, (*length).max(1), message),
+            SoundError::Score { line, message } => {
+                // Underline the whole line, without its indentation
+                let text = source.lines().nth(line.saturating_sub(1)).unwrap_or("");
+                let indent = text.chars().count() - text.trim_start().chars().count();
+                let length = text.trim().chars().count().max(1);
+                snippet(origin, source, *line, indent + 1, length, message)
+            }
+            other => format!("error: {}\n", other),
+        }
+    }
+}
+
+/// A header, the quoted line and a row of carets under `length` characters
+/// from `column`
+fn snippet(
+    origin: &str,
+    source: &str,
+    line: usize,
+    column: usize,
+    length: usize,
+    message: &str,
+) -> String {
+    let text = source.lines().nth(line.saturating_sub(1)).unwrap_or("");
+    let gutter = " ".repeat(line.to_string().len());
+    // Keep tabs so the caret lines up with the quoted text
+    let padding: String = text
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    format!(
+        "error: {message}\n{gutter}--> {origin}:{line}:{column}\n{gutter} |\n{line} | {text}\n{gutter} | {padding}{carets}\n",
+        carets = "^".repeat(length),
+    )
+}
+
+impl fmt::Display for SoundError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SoundError::Notation {
+                line,
+                column,
+                message,
+                ..
+            } => write!(f, "Line {}, column {}: {}", line, column, message),
+            SoundError::Score { line, message } => write!(f, "Line {}: {}", line, message),
+            SoundError::UnknownScale { name, known } => write!(
+                f,
+                "Unknown scale: {}. Try one of: {}",
+                name,
+                known.join(", ")
+            ),
+            SoundError::Io { path, message } => write!(f, "{}: {}", path, message),
+            SoundError::Multiple(errors) => {
+                let lines: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+                f.write_str(&lines.join("\n"))
+            }
+            SoundError::InvalidScale(message)
+            | SoundError::InvalidNote(message)
+            | SoundError::OutOfRange(message)
+            | SoundError::Audio(message)
+            | SoundError::Invalid(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for SoundError {}
+
+/// Config files and the parsers behind them report plain messages
+impl From<String> for SoundError {
+    fn from(message: String) -> Self {
+        SoundError::Invalid(message)
+    }
+}
+
+impl From<SoundError> for String {
+    fn from(error: SoundError) -> Self {
+        error.to_string()
+    }
+}
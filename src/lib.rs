@@ -16,9 +16,18 @@
 //!     .add_interval(interval::PERFECT_FIFTH, duration::quarter_note())
 //!     .add_interval(interval::OCTAVE, duration::half_note());
 //! ```
+//!
+//! [`Melody::builder`] sets the key, scale and tempo up front, after which
+//! notes can be added by scale degree, chords stacked, and melodies joined
+//! with [`Melody::append`] or layered with [`Melody::overlay`].
+//!
+//! Parsing and playback return [`SoundError`], which keeps the line and
+//! column of mistakes in notation; the `music` and `audio` modules never print
+//! or exit, leaving that to the command-line tools.
 
 pub mod audio;
 pub mod cli;
+pub mod error;
 // pub mod interval;
 // pub mod key;
 // pub mod melody;
@@ -26,8 +35,32 @@ pub mod music;
 // pub mod note;
 
 // Re-export main types for convenience
+pub use audio::oscillator::{NoteSource, Oscillator};
 pub use audio::square::Square;
+pub use audio::wave::WaveType;
+pub use error::SoundError;
+pub use music::abc::{format_abc, parse_abc, AbcTune};
+pub use music::duration::{duration, Ticks, PPQ};
+pub use music::interval;
 pub use music::key::Key;
-pub use music::melody::{Melody, MelodyConfig, NoteElement};
+pub use music::melody::{Melody, MelodyBuilder, MelodyConfig, NoteElement};
+pub use music::meter::Meter;
 pub use music::note::{MusicNote, Note};
-pub use music::util::{get_scale_by_name, parse_note_from_string, parse_note_notation};
+pub use music::scale::{Scale, ScaleRegistry};
+pub use music::score::{
+    format_score, parse_score, parse_score_for_scale, parse_score_in_meter, Score,
+};
+pub use music::util::{
+    format_aligned_notation, format_note_notation, get_scale_by_name, notation_ticks, parse_key,
+    parse_note_from_string, parse_note_notation, parse_note_notation_for_scale,
+    parse_note_notation_in_meter,
+};
+
+// Compile the examples in the README and scale reference as doctests
+#[cfg(doctest)]
+#[doc = include_str!("../README.md")]
+pub struct ReadmeDoctests;
+
+#[cfg(doctest)]
+#[doc = include_str!("../SCALES.md")]
+pub struct ScalesDoctests;
@@ -0,0 +1,1385 @@
+//! ABC notation, the plain-text format of most folk tune collections
+//!
+//! [`parse_abc`] reads the first tune of a file: its `K:` key and mode, `M:`
+//! meter, `L:` unit note length and `Q:` tempo, then the notes, rests, ties,
+//! broken rhythms, tuplets, bar lines, repeats and endings of the body, with
+//! one voice per `V:`. Every note becomes a degree of the key's mode; when a
+//! tune has notes outside the mode, all of it is read in the chromatic scale
+//! instead. Chords keep their first note, and grace notes, slurs, chord
+//! symbols and decorations other than staccato are skipped.
+//!
+//! [`format_abc`] writes a tune out again, spelling its notes for the key
+//! and writing accidentals only where the key signature does not give them.
+
+use super::{
+    duration::{duration::whole_note, Ticks, PPQ},
+    key::Key,
+    melody::NoteElement,
+    meter::Meter,
+    phrase::{expand, Position, Token},
+    spelling::{KeySpelling, Letter, PitchName},
+    util::{get_scale_by_name, with_bar_lines},
+};
+use crate::error::SoundError;
+use std::collections::HashMap;
+
+/// A tune read from (or to be written as) ABC notation
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub struct AbcTune {
+    /// The first `T:` field
+    pub title: Option<String>,
+    pub key: Key,
+    pub scale_name: String,
+    pub scale_intervals: Vec<i32>,
+    pub meter: Meter,
+    /// Length of one symbol; every note is a whole number of them
+    pub symbol_ticks: Ticks,
+    /// Quarter notes per minute, if the tune gives a tempo
+    pub bpm: Option<u32>,
+    /// Each voice's notes, with repeats written out
+    pub voices: Vec<Vec<NoteElement>>,
+}
+
+/// Longest run of `>` or `<` in a broken rhythm
+const MAX_BROKEN: usize = 3;
+
+/// ABC mode names, by their first three letters, and the scales they use
+const MODES: [(&str, &str); 9] = [
+    ("maj", "major"),
+    ("ion", "major"),
+    ("min", "minor"),
+    ("aeo", "minor"),
+    ("dor", "dorian"),
+    ("phr", "phrygian"),
+    ("lyd", "lydian"),
+    ("mix", "mixolydian"),
+    ("loc", "locrian"),
+];
+
+/// Read the first tune in ABC text. Every mistake is reported, each with
+/// the line and column it is at.
+pub fn parse_abc(text: &str) -> Result<AbcTune, SoundError> {
+    let mut reader = Reader::default();
+    let mut started = false;
+    let mut in_body = false;
+
+    for (index, raw) in text.lines().enumerate() {
+        let line = index + 1;
+        // `%` starts a comment, and `%%` lines are directives for typesetters
+        let content = raw.split('%').next().unwrap_or("").trim_end();
+        if content.trim().is_empty() {
+            // A blank line ends the tune
+            if in_body {
+                break;
+            }
+            continue;
+        }
+
+        let chars: Vec<char> = content.chars().collect();
+        if chars.len() >= 2 && chars[0].is_ascii_alphabetic() && chars[1] == ':' {
+            if chars[0] == 'X' && started {
+                // The next tune
+                break;
+            }
+            started = true;
+            let value: String = chars[2..].iter().collect();
+            let column = 3 + value.len() - value.trim_start().len();
+            reader.field(chars[0], value.trim(), (line, column), in_body);
+            if chars[0] == 'K' {
+                in_body = true;
+            }
+        } else if in_body {
+            reader.read(&chars, line);
+        } else if started {
+            // Text before the first field is about the file, not a tune
+            reader.error(
+                (line, 1),
+                "Notes must come after the K: field, which ends the tune's header",
+            );
+        }
+    }
+
+    if !in_body && reader.errors.is_empty() {
+        return Err(SoundError::Invalid(
+            "No ABC tune found (a tune needs a K: field before its notes)".to_string(),
+        ));
+    }
+    reader.finish()
+}
+
+/// Write a tune as ABC notation: a header, then each voice's notes, with a
+/// bar line every bar of the meter if the notes have none
+pub fn format_abc(tune: &AbcTune) -> Result<String, SoundError> {
+    let whole = whole_note();
+    if tune.symbol_ticks == 0 || !whole.is_multiple_of(tune.symbol_ticks) {
+        return Err(SoundError::Invalid(format!(
+            "A symbol of {} ticks is not a unit note length ABC can write",
+            tune.symbol_ticks
+        )));
+    }
+
+    let intervals: Vec<i32> = tune
+        .scale_intervals
+        .iter()
+        .copied()
+        .filter(|&interval| interval < 12)
+        .collect();
+    let spelling = KeySpelling::for_note(tune.key.root, &tune.scale_intervals);
+    let root = spelling.root();
+
+    // The key signature comes from the mode, or for other scales from the
+    // major or minor scale they are closest to
+    let fallback = if intervals.contains(&3) && !intervals.contains(&4) {
+        "minor"
+    } else {
+        "major"
+    };
+    let mode = MODES
+        .iter()
+        .map(|(_, scale)| *scale)
+        .find(|scale| {
+            get_scale_by_name(scale).is_ok_and(|scale| scale.intervals == tune.scale_intervals)
+        })
+        .unwrap_or(fallback);
+    let mode_intervals = get_scale_by_name(mode)?.intervals;
+    let signature_spelling = KeySpelling::new(root, &mode_intervals);
+    let signature: HashMap<Letter, i8> = mode_intervals
+        .iter()
+        .map(|&interval| {
+            let name = signature_spelling.name(interval);
+            (name.letter, name.accidental)
+        })
+        .collect();
+
+    let mut text = String::from("X:1\n");
+    text.push_str(&format!(
+        "T:{}\n",
+        tune.title.as_deref().unwrap_or("Untitled")
+    ));
+    text.push_str(&format!("M:{}\n", tune.meter));
+    text.push_str(&format!("L:1/{}\n", whole / tune.symbol_ticks));
+    if let Some(bpm) = tune.bpm {
+        text.push_str(&format!("Q:1/4={}\n", bpm));
+    }
+    text.push_str(&format!("K:{}{}\n", root, mode_name(mode)));
+
+    let bar_ticks = tune.meter.bar_ticks();
+    for (number, elements) in tune.voices.iter().enumerate() {
+        if tune.voices.len() > 1 {
+            text.push_str(&format!("V:{}\n", number + 1));
+        }
+        let has_bars = elements
+            .iter()
+            .any(|element| matches!(element, NoteElement::Bar));
+        let elements = if !has_bars && bar_ticks.is_multiple_of(tune.symbol_ticks) {
+            with_bar_lines(elements, (bar_ticks / tune.symbol_ticks) as usize)
+        } else {
+            elements.clone()
+        };
+        let mut writer = Writer {
+            spelling: &spelling,
+            signature: &signature,
+            intervals: &tune.scale_intervals,
+            key: 12 * tune.key.octave as i32 + tune.key.root.to_semitone(),
+            accidentals: HashMap::new(),
+            text: String::new(),
+            bars: 0,
+        };
+        writer.write(&elements)?;
+        text.push_str(&writer.text);
+    }
+    Ok(text)
+}
+
+/// How `K:` writes a mode after the root
+fn mode_name(scale: &str) -> &'static str {
+    match scale {
+        "minor" => "m",
+        "dorian" => "Dor",
+        "phrygian" => "Phr",
+        "lydian" => "Lyd",
+        "mixolydian" => "Mix",
+        "locrian" => "Loc",
+        _ => "",
+    }
+}
+
+/// A symbol of a voice before its notes are fitted to a scale and to one
+/// symbol length
+#[derive(Debug, Clone)]
+enum Symbol {
+    /// Semitones above the key's root, length, and whether it is staccato
+    Note(i32, Ticks, bool),
+    Rest(Ticks),
+    Token(Token),
+}
+
+#[derive(Debug, Default)]
+struct Voice {
+    id: String,
+    symbols: Vec<(Symbol, Position)>,
+    /// Accidentals written in the current bar, by letter and octave
+    accidentals: HashMap<(Letter, i32), i8>,
+    /// In an ending after `:|`, which runs to the next double bar or repeat
+    late_ending: bool,
+    /// The next note is staccato
+    staccato: bool,
+    /// Length of the next note from a broken rhythm, as a fraction
+    broken: Option<(Ticks, Ticks)>,
+    /// Tuplet in progress: it fits `p` notes into the time of `q`, for
+    /// `r` more notes
+    tuplet: Option<(Ticks, Ticks, usize)>,
+}
+
+/// The key, meter and unit length in force, with the voices read so far
+struct Reader {
+    title: Option<String>,
+    /// Root and scale from `K:`, and the root's pitch counted from C0
+    root: PitchName,
+    scale: &'static str,
+    key: i32,
+    /// Accidental of each letter in the key signature
+    signature: HashMap<Letter, i8>,
+    meter: Meter,
+    unit: Option<Ticks>,
+    bpm: Option<u32>,
+    voices: Vec<Voice>,
+    current: Option<usize>,
+    errors: Vec<SoundError>,
+}
+
+impl Default for Reader {
+    fn default() -> Self {
+        let root = PitchName::new(Letter::C, 0);
+        Self {
+            title: None,
+            root,
+            scale: "major",
+            key: 48,
+            signature: HashMap::new(),
+            meter: Meter::default(),
+            unit: None,
+            bpm: None,
+            voices: Vec::new(),
+            current: None,
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl Reader {
+    fn error(&mut self, (line, column): Position, message: &str) {
+        self.errors.push(SoundError::Notation {
+            line,
+            column,
+            length: 1,
+            message: message.to_string(),
+        });
+    }
+
+    /// Length of a unit note: `L:`, or an eighth unless the meter is
+    /// shorter than 3/4
+    fn unit(&self) -> Ticks {
+        self.unit.unwrap_or_else(|| {
+            if 4 * self.meter.beats < 3 * self.meter.unit {
+                whole_note() / 16
+            } else {
+                whole_note() / 8
+            }
+        })
+    }
+
+    /// Apply an information field such as `M:6/8`, from a line of its own
+    /// or written inline as `[M:6/8]`
+    fn field(&mut self, name: char, value: &str, position: Position, in_body: bool) {
+        match name {
+            'T' if self.title.is_none() && !value.is_empty() => {
+                self.title = Some(value.to_string());
+            }
+            'M' => match value {
+                "C" => self.meter = Meter::new(4, 4),
+                "C|" => self.meter = Meter::new(2, 2),
+                "none" | "" => {}
+                _ => match Meter::parse(value) {
+                    Ok(meter) => self.meter = meter,
+                    Err(error) => self.error(position, &error.to_string()),
+                },
+            },
+            'L' => match parse_fraction(value).and_then(|(num, den)| exact(whole_note(), num, den))
+            {
+                Some(ticks) if ticks > 0 => self.unit = Some(ticks),
+                _ => self.error(
+                    position,
+                    &format!(
+                        "Invalid unit note length '{}'. Use a fraction such as 1/8",
+                        value
+                    ),
+                ),
+            },
+            'Q' => match self.tempo(value) {
+                Some(bpm) => {
+                    if self.bpm.is_none() {
+                        self.bpm = Some(bpm);
+                    }
+                }
+                None => self.error(
+                    position,
+                    &format!(
+                        "Invalid tempo '{}'. Use beats per minute such as 1/4=120",
+                        value
+                    ),
+                ),
+            },
+            'K' => match parse_key_field(value) {
+                // Only a clef or other settings, no key
+                Ok(None) if in_body => {}
+                Ok(key) => {
+                    let (root, scale, extra) =
+                        key.unwrap_or((PitchName::new(Letter::C, 0), "major", Vec::new()));
+                    if in_body {
+                        if root != self.root || scale != self.scale || !extra.is_empty() {
+                            self.error(
+                                position,
+                                "Key changes in the middle of a tune are not supported",
+                            );
+                        }
+                    } else {
+                        self.set_key(root, scale, &extra);
+                    }
+                }
+                Err(message) => self.error(position, &message),
+            },
+            'V' => {
+                let id = value.split_whitespace().next().unwrap_or("").to_string();
+                let index = match self.voices.iter().position(|voice| voice.id == id) {
+                    Some(index) => index,
+                    None => {
+                        self.voices.push(Voice {
+                            id,
+                            ..Voice::default()
+                        });
+                        self.voices.len() - 1
+                    }
+                };
+                self.current = Some(index);
+            }
+            // Titles after the first, composer, lyrics, notes and the like
+            _ => {}
+        }
+    }
+
+    fn set_key(&mut self, root: PitchName, scale: &'static str, extra: &[(Letter, i8)]) {
+        self.root = root;
+        self.scale = scale;
+        // The octave of middle C, so that `C` is C4
+        self.key = 48 + root.letter.semitone() + root.accidental as i32;
+        let intervals = get_scale_by_name(scale)
+            .map(|scale| scale.intervals)
+            .unwrap_or_default();
+        let spelling = KeySpelling::new(root, &intervals);
+        self.signature = intervals
+            .iter()
+            .map(|&interval| {
+                let name = spelling.name(interval);
+                (name.letter, name.accidental)
+            })
+            .collect();
+        for (letter, accidental) in extra {
+            self.signature.insert(*letter, *accidental);
+        }
+    }
+
+    /// Quarter notes per minute from `Q:1/4=120`, `Q:3/8=40` or an old
+    /// style `Q:120`, counted in unit notes
+    fn tempo(&self, value: &str) -> Option<u32> {
+        // Drop quoted text such as "Allegro"
+        let value: String = value.split('"').step_by(2).collect();
+        let (beat, count) = match value.split_once('=') {
+            Some((beat, count)) => {
+                let mut ticks = 0;
+                for part in beat.split_whitespace() {
+                    let (num, den) = parse_fraction(part)?;
+                    ticks += exact(whole_note(), num, den)?;
+                }
+                (ticks, count.trim())
+            }
+            None => (self.unit(), value.trim()),
+        };
+        let count: u64 = count.parse().ok()?;
+        let bpm = ((count * beat + PPQ / 2) / PPQ) as u32;
+        (bpm > 0).then_some(bpm)
+    }
+
+    /// The voice notes go to, starting one if the tune has no `V:`
+    fn voice(&mut self) -> &mut Voice {
+        let index = *self.current.get_or_insert_with(|| {
+            self.voices.push(Voice::default());
+            self.voices.len() - 1
+        });
+        &mut self.voices[index]
+    }
+
+    /// Read one line of a tune's body
+    fn read(&mut self, chars: &[char], line: usize) {
+        let mut index = 0;
+        while index < chars.len() {
+            let ch = chars[index];
+            let position = (line, index + 1);
+            let next = chars.get(index + 1).copied();
+            match ch {
+                // Spacing, line continuation and beaming marks
+                ' ' | '\t' | '`' | '\\' | 'y' | ')' => index += 1,
+                // Chord symbols and annotations
+                '"' => index = self.skip_to(chars, index, '"', position),
+                // Grace notes
+                '{' => index = self.skip_to(chars, index, '}', position),
+                '!' | '+' => {
+                    let name: String = chars[index + 1..]
+                        .iter()
+                        .take_while(|&&c| c != ch)
+                        .collect();
+                    if name == "staccato" {
+                        self.voice().staccato = true;
+                    }
+                    index = self.skip_to(chars, index, ch, position);
+                }
+                '.' => {
+                    self.voice().staccato = true;
+                    index += 1;
+                }
+                // Rolls, trills, fermatas, bowings and other decorations
+                '~' | 'H' | 'L' | 'M' | 'O' | 'P' | 'S' | 'T' | 'u' | 'v' => index += 1,
+                '(' if next.is_some_and(|c| c.is_ascii_digit()) => {
+                    index = self.tuplet(chars, index + 1);
+                }
+                // Slurs
+                '(' => index += 1,
+                '&' => {
+                    self.error(
+                        position,
+                        "Voice overlays (&) are not supported; use V: voices",
+                    );
+                    index += 1;
+                }
+                '-' => {
+                    let voice = self.voice();
+                    if matches!(voice.symbols.last(), Some((Symbol::Note(..), _))) {
+                        voice
+                            .symbols
+                            .push((Symbol::Token(Token::Element(NoteElement::Tie)), position));
+                    } else {
+                        self.error(position, "A tie (-) must come right after a note");
+                    }
+                    index += 1;
+                }
+                '>' | '<' => {
+                    let count = chars[index..].iter().take_while(|&&c| c == ch).count();
+                    self.broken(ch, count, position);
+                    index += count;
+                }
+                '|' | ':' => index = self.bar_line(chars, index, line),
+                '[' if next == Some('|') => index = self.bar_line(chars, index, line),
+                '[' if next.is_some_and(|c| c.is_ascii_digit()) => {
+                    index = self.ending(chars, index + 1, position);
+                }
+                '[' if chars.get(index + 2) == Some(&':')
+                    && next.is_some_and(|c| c.is_ascii_alphabetic()) =>
+                {
+                    let value: String = chars[index + 3..]
+                        .iter()
+                        .take_while(|&&c| c != ']')
+                        .collect();
+                    self.field(next.unwrap(), value.trim(), (line, index + 4), true);
+                    index = self.skip_to(chars, index, ']', position);
+                }
+                '[' => index = self.chord(chars, index + 1, line),
+                // A stray end of a chord or an ending
+                ']' => index += 1,
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' => {
+                    index = match self.note(chars, index, line) {
+                        Some((pitch, ticks, end)) => {
+                            self.push_note(pitch, ticks, position);
+                            end
+                        }
+                        None => skip_note(chars, index),
+                    };
+                }
+                'z' | 'x' => {
+                    let (num, den, end) = parse_length(chars, index + 1);
+                    if let Some(ticks) = self.length(num, den, position) {
+                        self.voice().symbols.push((Symbol::Rest(ticks), position));
+                    }
+                    index = end;
+                }
+                // Rests of whole bars
+                'Z' | 'X' => {
+                    let digits: String = chars[index + 1..]
+                        .iter()
+                        .take_while(|c| c.is_ascii_digit())
+                        .collect();
+                    let bars: Ticks = if digits.is_empty() {
+                        1
+                    } else {
+                        digits.parse().unwrap_or(0)
+                    };
+                    match bars.checked_mul(self.meter.bar_ticks()) {
+                        Some(ticks) if ticks > 0 => {
+                            self.voice().symbols.push((Symbol::Rest(ticks), position));
+                        }
+                        _ => self.error(position, "A rest of whole bars needs a count from 1"),
+                    }
+                    index += 1 + digits.len();
+                }
+                _ => {
+                    self.error(position, &format!("Unexpected '{}' in the tune", ch));
+                    index += 1;
+                }
+            }
+        }
+    }
+
+    /// Index just past the `close` that ends the part starting at `index`
+    fn skip_to(&mut self, chars: &[char], index: usize, close: char, position: Position) -> usize {
+        match chars[index + 1..].iter().position(|&c| c == close) {
+            Some(offset) => index + offset + 2,
+            None => {
+                self.error(position, &format!("This is never closed with {}", close));
+                chars.len()
+            }
+        }
+    }
+
+    /// A note with its accidental, octave marks and length: its pitch above
+    /// the root, its length and the index after it
+    fn note(&mut self, chars: &[char], start: usize, line: usize) -> Option<(i32, Ticks, usize)> {
+        let (pitch, num, den, end) = self.pitch(chars, start, line)?;
+        let ticks = self.length(num, den, (line, start + 1))?;
+        Some((pitch, ticks, end))
+    }
+
+    /// A note as written, before any tuplet or broken rhythm: its pitch above
+    /// the root, its length as `num/den` unit notes and the index after it
+    fn pitch(
+        &mut self,
+        chars: &[char],
+        start: usize,
+        line: usize,
+    ) -> Option<(i32, Ticks, Ticks, usize)> {
+        let position = (line, start + 1);
+        let mut index = start;
+        let mut accidental = None;
+        while let Some(&c) = chars.get(index) {
+            let change = match c {
+                '^' => 1,
+                '_' => -1,
+                '=' => 0,
+                _ => break,
+            };
+            accidental = Some(accidental.unwrap_or(0) + change);
+            index += 1;
+        }
+        let Some(letter) = chars.get(index).and_then(|&c| {
+            c.is_ascii_alphabetic()
+                .then(|| Letter::from_char(c))
+                .flatten()
+        }) else {
+            self.error(position, "An accidental must be followed by a note");
+            return None;
+        };
+        let mut octave = if chars[index].is_ascii_lowercase() {
+            5
+        } else {
+            4
+        };
+        index += 1;
+        while let Some(&c) = chars.get(index) {
+            match c {
+                '\'' => octave += 1,
+                ',' => octave -= 1,
+                _ => break,
+            }
+            index += 1;
+        }
+        let (num, den, end) = parse_length(chars, index);
+
+        // An accidental lasts to the end of the bar, for that octave
+        let signature = self.signature.get(&letter).copied().unwrap_or(0);
+        let voice = self.voice();
+        let accidental = match accidental {
+            Some(accidental) => {
+                voice.accidentals.insert((letter, octave), accidental);
+                accidental
+            }
+            None => voice
+                .accidentals
+                .get(&(letter, octave))
+                .copied()
+                .unwrap_or(signature),
+        };
+        let pitch = 12 * octave + letter.semitone() + accidental as i32 - self.key;
+        Some((pitch, num, den, end))
+    }
+
+    fn push_note(&mut self, pitch: i32, ticks: Ticks, position: Position) {
+        let voice = self.voice();
+        let staccato = std::mem::take(&mut voice.staccato);
+        voice
+            .symbols
+            .push((Symbol::Note(pitch, ticks, staccato), position));
+    }
+
+    /// Length of a note or rest written as `num/den` unit notes, in any
+    /// tuplet or broken rhythm it is part of
+    fn length(&mut self, num: Ticks, den: Ticks, position: Position) -> Option<Ticks> {
+        let unit = self.unit();
+        let voice = self.voice();
+        if num == 0 {
+            self.error(position, "A note or rest cannot have a length of 0");
+            return None;
+        }
+        let mut ticks = exact(unit, num, den);
+        if let Some((p, q, left)) = voice.tuplet {
+            ticks = ticks.and_then(|ticks| exact(ticks, q, p));
+            voice.tuplet = (left > 1).then_some((p, q, left - 1));
+        }
+        if let Some((num, den)) = voice.broken.take() {
+            ticks = ticks.and_then(|ticks| exact(ticks, num, den));
+        }
+        match ticks {
+            Some(ticks) if ticks > 0 => Some(ticks),
+            _ => {
+                self.error(position, "This note is too short to time exactly");
+                None
+            }
+        }
+    }
+
+    /// `(3`, `(3:2` or `(3:2:3`: the next notes played in the time of fewer
+    fn tuplet(&mut self, chars: &[char], mut index: usize) -> usize {
+        let mut numbers = Vec::new();
+        loop {
+            let digits: String = chars[index..]
+                .iter()
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            index += digits.len();
+            numbers.push(digits.parse::<Ticks>().ok());
+            if chars.get(index) == Some(&':') && numbers.len() < 3 {
+                index += 1;
+            } else {
+                break;
+            }
+        }
+        let p = numbers[0].unwrap_or(3).max(1);
+        let compound = self.meter.beats.is_multiple_of(3) && self.meter.beats > 3;
+        let q = numbers.get(1).copied().flatten().unwrap_or(match p {
+            3 | 6 => 2,
+            2 | 4 | 8 => 3,
+            _ if compound => 3,
+            _ => 2,
+        });
+        let r = numbers.get(2).copied().flatten().unwrap_or(p) as usize;
+        self.voice().tuplet = Some((p, q, r));
+        index
+    }
+
+    /// `a>b` makes the first note dotted and the second half as long; `<`
+    /// the other way round, `>>` double dotted and `>>>` triple dotted
+    fn broken(&mut self, ch: char, count: usize, position: Position) {
+        if count > MAX_BROKEN {
+            self.error(
+                position,
+                &format!(
+                    "A broken rhythm is at most {}",
+                    ch.to_string().repeat(MAX_BROKEN)
+                ),
+            );
+            return;
+        }
+        let den: Ticks = 1 << count;
+        let (long, short) = ((2 * den - 1, den), (1, den));
+        let (before, after) = if ch == '>' {
+            (long, short)
+        } else {
+            (short, long)
+        };
+        let voice = self.voice();
+        let last = voice
+            .symbols
+            .iter_mut()
+            .rev()
+            .find_map(|(symbol, _)| match symbol {
+                Symbol::Note(_, ticks, _) | Symbol::Rest(ticks) => Some(ticks),
+                Symbol::Token(_) => None,
+            });
+        match last.map(|ticks| (exact(*ticks, before.0, before.1), ticks)) {
+            Some((Some(length), ticks)) => {
+                *ticks = length;
+                voice.broken = Some(after);
+            }
+            Some((None, _)) => self.error(position, "This note is too short to time exactly"),
+            None => self.error(
+                position,
+                "A broken rhythm (> or <) must come between two notes",
+            ),
+        }
+    }
+
+    /// Bar lines and repeat signs: `|`, `||`, `|]`, `[|`, `|:`, `:|`, `::`,
+    /// followed by an ending number as in `:|2`
+    fn bar_line(&mut self, chars: &[char], start: usize, line: usize) -> usize {
+        let position = (line, start + 1);
+        let mut index = start;
+        while let Some(&c) = chars.get(index) {
+            let part = match c {
+                '|' | ':' => true,
+                '[' => chars.get(index + 1) == Some(&'|'),
+                ']' => index > start && chars[index - 1] == '|',
+                _ => false,
+            };
+            if !part {
+                break;
+            }
+            index += 1;
+        }
+        let sign: String = chars[start..index].iter().collect();
+        let closes = sign.starts_with(':');
+        let opens = sign.ends_with(':') && sign.len() > 1;
+        if !sign.contains('|') && sign != "::" {
+            self.error(position, &format!("Unexpected '{}' in the tune", sign));
+            return index;
+        }
+
+        let voice = self.voice();
+        voice.accidentals.clear();
+        let double = sign.trim_matches(':') != "|";
+        if voice.late_ending && (double || closes || opens) {
+            voice.late_ending = false;
+            voice
+                .symbols
+                .push((Symbol::Token(Token::EndingEnd), position));
+        }
+        if closes {
+            voice
+                .symbols
+                .push((Symbol::Token(Token::RepeatEnd(None)), position));
+        }
+        if opens {
+            voice
+                .symbols
+                .push((Symbol::Token(Token::RepeatStart), position));
+        }
+        if !closes && !opens {
+            voice
+                .symbols
+                .push((Symbol::Token(Token::Element(NoteElement::Bar)), position));
+        }
+
+        if chars.get(index).is_some_and(|c| c.is_ascii_digit()) {
+            return self.ending(chars, index, (line, index + 1));
+        }
+        index
+    }
+
+    /// An ending's passes, such as `1`, `1,3` or `1-3`
+    fn ending(&mut self, chars: &[char], start: usize, position: Position) -> usize {
+        let label: String = chars[start..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit() || **c == ',' || **c == '-')
+            .collect();
+        let mut passes = Vec::new();
+        for part in label.split(',') {
+            let range = match part.split_once('-') {
+                Some((first, last)) => first.parse().ok().zip(last.parse().ok()),
+                None => part.parse::<usize>().ok().map(|pass| (pass, pass)),
+            };
+            match range {
+                Some((first, last)) if first > 0 && first <= last => passes.extend(first..=last),
+                _ => {
+                    self.error(
+                        position,
+                        "Number the ending with its passes, e.g. [1 or [1,2",
+                    );
+                    return start + label.len();
+                }
+            }
+        }
+        let voice = self.voice();
+        voice.late_ending = matches!(
+            voice.symbols.last(),
+            Some((Symbol::Token(Token::RepeatEnd(_)), _))
+        );
+        voice
+            .symbols
+            .push((Symbol::Token(Token::EndingStart(passes)), position));
+        start + label.len()
+    }
+
+    /// A chord such as `[CEG]2`, played as its first note. The chord takes
+    /// one place in a tuplet or broken rhythm, however many notes it has.
+    fn chord(&mut self, chars: &[char], mut index: usize, line: usize) -> usize {
+        let start = (line, index);
+        let mut first = None;
+        while let Some(&c) = chars.get(index) {
+            if c == ']' {
+                break;
+            }
+            if matches!(c, '^' | '_' | '=' | 'A'..='G' | 'a'..='g') {
+                let note = self.pitch(chars, index, line);
+                index = match note {
+                    Some((_, _, _, end)) => end,
+                    None => skip_note(chars, index),
+                };
+                if first.is_none() {
+                    first = note;
+                }
+            } else {
+                // Decorations and ties inside the chord
+                index += 1;
+            }
+        }
+        if index >= chars.len() {
+            self.error(start, "This chord is never closed with ]");
+            return index;
+        }
+        let (num, den, end) = parse_length(chars, index + 1);
+        match first {
+            Some((pitch, note_num, note_den, _)) => {
+                let length = self.length(
+                    note_num.saturating_mul(num),
+                    note_den.saturating_mul(den),
+                    start,
+                );
+                if let Some(ticks) = length {
+                    self.push_note(pitch, ticks, start);
+                }
+            }
+            None => self.error(start, "This chord has no notes"),
+        }
+        end
+    }
+
+    /// Fit every voice to a scale and one symbol length
+    fn finish(mut self) -> Result<AbcTune, SoundError> {
+        let notes = || {
+            self.voices
+                .iter()
+                .flat_map(|voice| voice.symbols.iter())
+                .filter_map(|(symbol, _)| match symbol {
+                    Symbol::Note(pitch, ticks, _) => Some((Some(*pitch), *ticks)),
+                    Symbol::Rest(ticks) => Some((None, *ticks)),
+                    Symbol::Token(_) => None,
+                })
+        };
+        let symbol_ticks = notes().map(|(_, ticks)| ticks).reduce(gcd);
+        if symbol_ticks.is_none() && self.errors.is_empty() {
+            return Err(SoundError::Invalid("The ABC tune has no notes".to_string()));
+        }
+        // Zero lengths are reported as they are read, but must never be divided by
+        if symbol_ticks == Some(0) {
+            self.errors.push(SoundError::Invalid(
+                "The ABC tune's notes have no length".to_string(),
+            ));
+            return Err(SoundError::from_errors(self.errors));
+        }
+        let symbol_ticks = symbol_ticks.unwrap_or(1);
+
+        // Notes outside the mode need every semitone
+        let mode = get_scale_by_name(self.scale)?;
+        let in_mode = notes()
+            .filter_map(|(pitch, _)| pitch)
+            .all(|pitch| mode.intervals.contains(&pitch.rem_euclid(12)));
+        let scale = if in_mode {
+            mode
+        } else {
+            get_scale_by_name("chromatic")?
+        };
+
+        let mut voices = Vec::new();
+        for voice in &self.voices {
+            let mut tokens: Vec<(Token, Position)> = Vec::new();
+            for (symbol, position) in &voice.symbols {
+                let (first, count) = match symbol {
+                    Symbol::Note(pitch, ticks, staccato) => {
+                        let degree = scale
+                            .intervals
+                            .iter()
+                            .position(|&interval| interval == pitch.rem_euclid(12))
+                            .unwrap_or(0);
+                        tokens.push((
+                            Token::Element(NoteElement::Note(degree + 1, pitch.div_euclid(12))),
+                            *position,
+                        ));
+                        if *staccato {
+                            tokens.push((Token::Element(NoteElement::Staccato), *position));
+                        }
+                        (NoteElement::Sustain, ticks / symbol_ticks - 1)
+                    }
+                    Symbol::Rest(ticks) => (NoteElement::Rest, ticks / symbol_ticks),
+                    Symbol::Token(token) => {
+                        tokens.push((token.clone(), *position));
+                        continue;
+                    }
+                };
+                for _ in 0..count {
+                    tokens.push((Token::Element(first.clone()), *position));
+                }
+            }
+            let elements = expand(&tokens, &mut self.errors);
+            voices.push(elements.into_iter().map(|(element, _)| element).collect());
+        }
+
+        if !self.errors.is_empty() {
+            return Err(SoundError::from_errors(self.errors));
+        }
+        Ok(AbcTune {
+            title: self.title,
+            key: Key::new(self.root.note(), self.key.div_euclid(12) as u8),
+            scale_name: scale.name,
+            scale_intervals: scale.intervals,
+            meter: self.meter,
+            symbol_ticks,
+            bpm: self.bpm,
+            voices,
+        })
+    }
+}
+
+/// A key's root and scale, with any accidentals added to its signature
+type KeyField = (PitchName, &'static str, Vec<(Letter, i8)>);
+
+/// Read a `K:` field: the root and scale with any extra accidentals such as
+/// `^f`, or nothing if it only holds settings such as `clef=bass`
+fn parse_key_field(value: &str) -> Result<Option<KeyField>, String> {
+    let mut words = value
+        .split_whitespace()
+        .filter(|word| !word.contains('='))
+        .peekable();
+    let Some(first) = words.next() else {
+        return Ok(None);
+    };
+    if first.eq_ignore_ascii_case("none") {
+        return Ok(Some((PitchName::new(Letter::C, 0), "major", Vec::new())));
+    }
+    if first.eq_ignore_ascii_case("hp") {
+        return Err("Highland pipe keys (K:HP) are not supported".to_string());
+    }
+
+    let invalid = || {
+        format!(
+            "Invalid key '{}'. Use a root and mode, e.g. G, Em or D mix",
+            value
+        )
+    };
+    let mut chars = first.chars();
+    let letter = chars
+        .next()
+        .filter(|c| c.is_ascii_uppercase())
+        .and_then(Letter::from_char)
+        .ok_or_else(invalid)?;
+    let rest = chars.as_str();
+    let (accidental, mode) = match rest.chars().next() {
+        Some('#') => (1, &rest[1..]),
+        Some('b') => (-1, &rest[1..]),
+        _ => (0, rest),
+    };
+
+    // The mode may be attached (`Em`, `Ador`) or the next word (`D mix`)
+    let mode = if mode.is_empty() {
+        match words.peek() {
+            Some(word) if word.chars().all(|c| c.is_ascii_alphabetic()) => words.next().unwrap(),
+            _ => "",
+        }
+    } else {
+        mode
+    };
+    let mode = mode.to_lowercase();
+    let scale = if mode.is_empty() {
+        "major"
+    } else if mode == "m" {
+        "minor"
+    } else {
+        let prefix = mode.get(..3).unwrap_or(&mode);
+        MODES
+            .iter()
+            .find(|(name, _)| *name == prefix)
+            .map(|(_, scale)| *scale)
+            .ok_or_else(invalid)?
+    };
+
+    let mut extra = Vec::new();
+    for word in words {
+        if word.eq_ignore_ascii_case("exp") {
+            continue;
+        }
+        let accidental = match word.trim_end_matches(|c: char| c.is_ascii_alphabetic()) {
+            "^" => 1,
+            "^^" => 2,
+            "_" => -1,
+            "__" => -2,
+            "=" => 0,
+            _ => return Err(invalid()),
+        };
+        let letter = word
+            .chars()
+            .last()
+            .and_then(Letter::from_char)
+            .ok_or_else(invalid)?;
+        extra.push((letter, accidental));
+    }
+    Ok(Some((PitchName::new(letter, accidental), scale, extra)))
+}
+
+/// A length such as `2`, `/2`, `/`, `//` or `3/2` after a note, as a
+/// fraction of the unit, and the index after it
+fn parse_length(chars: &[char], mut index: usize) -> (Ticks, Ticks, usize) {
+    let digits = |index: usize| -> String {
+        chars[index..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .collect()
+    };
+    let num_text = digits(index);
+    index += num_text.len();
+    let num = num_text.parse().unwrap_or(1);
+    let mut den: Ticks = 1;
+    while chars.get(index) == Some(&'/') {
+        index += 1;
+        let den_text = digits(index);
+        index += den_text.len();
+        den = den.saturating_mul(den_text.parse().unwrap_or(2));
+    }
+    (num, den, index)
+}
+
+/// The index after a note that could not be read
+fn skip_note(chars: &[char], index: usize) -> usize {
+    index
+        + 1
+        + chars[index + 1..]
+            .iter()
+            .take_while(|c| matches!(c, '\'' | ',' | '/' | '0'..='9'))
+            .count()
+}
+
+/// A fraction such as `1/8`, or a whole number
+fn parse_fraction(text: &str) -> Option<(Ticks, Ticks)> {
+    match text.trim().split_once('/') {
+        Some((num, den)) => Some((num.trim().parse().ok()?, den.trim().parse().ok()?)),
+        None => Some((text.trim().parse().ok()?, 1)),
+    }
+}
+
+/// `ticks * num / den`, if that is a whole number of ticks
+fn exact(ticks: Ticks, num: Ticks, den: Ticks) -> Option<Ticks> {
+    let scaled = ticks.checked_mul(num)?;
+    (den > 0 && scaled.is_multiple_of(den)).then(|| scaled / den)
+}
+
+fn gcd(a: Ticks, b: Ticks) -> Ticks {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Writes one voice's notes as ABC
+struct Writer<'a> {
+    spelling: &'a KeySpelling,
+    signature: &'a HashMap<Letter, i8>,
+    intervals: &'a [i32],
+    /// The key's root counted in semitones from C0
+    key: i32,
+    /// Accidentals written in the current bar, by letter and octave
+    accidentals: HashMap<(Letter, i32), i8>,
+    text: String,
+    /// Bars written on the current line
+    bars: usize,
+}
+
+impl Writer<'_> {
+    fn write(&mut self, elements: &[NoteElement]) -> Result<(), SoundError> {
+        let mut index = 0;
+        while index < elements.len() {
+            match &elements[index] {
+                NoteElement::Note(position, octave) => {
+                    let interval = position
+                        .checked_sub(1)
+                        .and_then(|index| self.intervals.get(index))
+                        .map(|interval| interval + octave * 12)
+                        .ok_or_else(|| {
+                            SoundError::OutOfRange(format!(
+                                "Note position {} is out of range for this scale",
+                                position
+                            ))
+                        })?;
+
+                    // Sustains and marks that belong to this note; a note
+                    // held across a bar line is tied over it
+                    let mut length = 1;
+                    let mut staccato = false;
+                    let mut tie = false;
+                    index += 1;
+                    while let Some(element) = elements.get(index) {
+                        match element {
+                            NoteElement::Sustain => length += 1,
+                            NoteElement::Staccato => staccato = true,
+                            NoteElement::Legato => staccato = false,
+                            NoteElement::Tie => {
+                                tie = true;
+                                index += 1;
+                                break;
+                            }
+                            NoteElement::Bar => {
+                                let held = elements[index..]
+                                    .iter()
+                                    .find(|element| !matches!(element, NoteElement::Bar));
+                                if !matches!(held, Some(NoteElement::Sustain)) {
+                                    break;
+                                }
+                                self.note(interval, length, staccato, true);
+                                self.bar();
+                                length = 0;
+                                staccato = false;
+                            }
+                            NoteElement::Note(..) | NoteElement::Rest => break,
+                        }
+                        index += 1;
+                    }
+                    if length > 0 {
+                        self.note(interval, length, staccato, tie);
+                    }
+                }
+                NoteElement::Rest | NoteElement::Sustain => {
+                    // Sustains without a note before them are rests
+                    let length = elements[index..]
+                        .iter()
+                        .take_while(|element| {
+                            matches!(element, NoteElement::Rest | NoteElement::Sustain)
+                        })
+                        .count();
+                    self.text.push('z');
+                    self.length(length);
+                    index += length;
+                }
+                NoteElement::Bar => {
+                    self.bar();
+                    index += 1;
+                }
+                NoteElement::Tie | NoteElement::Staccato | NoteElement::Legato => index += 1,
+            }
+        }
+        self.text
+            .truncate(self.text.trim_end_matches([' ', '|', '\n']).len());
+        self.text.push_str(" |]\n");
+        Ok(())
+    }
+
+    fn note(&mut self, interval: i32, length: usize, staccato: bool, tie: bool) {
+        let name = self.spelling.name(interval);
+        let pitch = self.key + interval;
+        let octave = (pitch - name.letter.semitone() - name.accidental as i32).div_euclid(12);
+
+        let written = self
+            .accidentals
+            .get(&(name.letter, octave))
+            .or(self.signature.get(&name.letter))
+            .copied()
+            .unwrap_or(0);
+        if staccato {
+            self.text.push('.');
+        }
+        if name.accidental != written {
+            let symbol = match name.accidental {
+                2 => "^^",
+                1 => "^",
+                0 => "=",
+                -1 => "_",
+                _ => "__",
+            };
+            self.text.push_str(symbol);
+            self.accidentals
+                .insert((name.letter, octave), name.accidental);
+        }
+
+        let letter = format!("{:?}", name.letter);
+        if octave >= 5 {
+            self.text.push_str(&letter.to_lowercase());
+            self.text.push_str(&"'".repeat((octave - 5) as usize));
+        } else {
+            self.text.push_str(&letter);
+            self.text
+                .push_str(&",".repeat((4 - octave).max(0) as usize));
+        }
+        self.length(length);
+        if tie {
+            self.text.push('-');
+        }
+    }
+
+    fn length(&mut self, length: usize) {
+        if length != 1 {
+            self.text.push_str(&length.to_string());
+        }
+    }
+
+    /// A bar line, starting a new line of music every four bars
+    fn bar(&mut self) {
+        self.accidentals.clear();
+        if self.text.is_empty() {
+            return;
+        }
+        self.bars += 1;
+        if self.bars.is_multiple_of(4) {
+            self.text.push_str(" |\n");
+        } else {
+            self.text.push_str(" | ");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use NoteElement::{Bar, Note, Sustain};
+
+    fn voice(text: &str) -> Vec<NoteElement> {
+        let tune = parse_abc(text).unwrap();
+        tune.voices[0].clone()
+    }
+
+    fn tune_ticks(text: &str) -> Ticks {
+        parse_abc(text).unwrap().symbol_ticks
+    }
+
+    #[test]
+    fn accidentals_outside_the_mode_switch_to_chromatic() {
+        let tune = parse_abc("X:1\nK:C\nL:1/8\nC^CD|").unwrap();
+        assert_eq!(tune.scale_intervals.len(), 13);
+        assert_eq!(tune.voices[0], [Note(1, 0), Note(2, 0), Note(3, 0), Bar]);
+
+        let tune = parse_abc("X:1\nK:C\nL:1/8\nCDE|").unwrap();
+        assert_eq!(tune.scale_name, "Major");
+    }
+
+    #[test]
+    fn broken_rhythm_dots_the_first_or_second_note() {
+        assert_eq!(tune_ticks("K:C\nL:1/8\nC>D|"), whole_note() / 16);
+        assert_eq!(tune_ticks("K:C\nL:1/8\nC>>>D|"), whole_note() / 64);
+        assert_eq!(
+            voice("K:C\nL:1/8\n[CE]>D|"),
+            [Note(1, 0), Sustain, Sustain, Note(2, 0), Bar]
+        );
+        assert_eq!(
+            voice("K:C\nL:1/8\nC>D C<D|"),
+            [
+                Note(1, 0),
+                Sustain,
+                Sustain,
+                Note(2, 0),
+                Note(1, 0),
+                Note(2, 0),
+                Sustain,
+                Sustain,
+                Bar
+            ]
+        );
+    }
+
+    #[test]
+    fn tuplets_share_the_time_of_fewer_notes() {
+        assert_eq!(tune_ticks("K:C\nL:1/8\n(3CDE F2|"), whole_note() / 12);
+        // A chord is one note of the tuplet
+        assert_eq!(
+            voice("L:1/8\nK:C\n(3[CEG]AB c2|"),
+            [
+                Note(1, 0),
+                Note(6, 0),
+                Note(7, 0),
+                Note(1, 1),
+                Sustain,
+                Sustain,
+                Bar
+            ]
+        );
+        assert_eq!(
+            voice("K:C\nL:1/8\n(3CDE F2|"),
+            [
+                Note(1, 0),
+                Note(2, 0),
+                Note(3, 0),
+                Note(4, 0),
+                Sustain,
+                Sustain,
+                Bar
+            ]
+        );
+    }
+
+    #[test]
+    fn endings_and_double_repeats_are_written_out() {
+        assert_eq!(
+            voice("K:C\nL:1/4\n|:C|1D:|2E|"),
+            [
+                Bar,
+                Note(1, 0),
+                Bar,
+                Note(2, 0),
+                Bar,
+                Note(1, 0),
+                Bar,
+                Note(3, 0),
+                Bar
+            ]
+        );
+        assert_eq!(
+            voice("K:C\nL:1/4\n|:C::D:|"),
+            [Note(1, 0), Note(1, 0), Note(2, 0), Note(2, 0)]
+        );
+    }
+
+    #[test]
+    fn commas_and_apostrophes_shift_octaves() {
+        assert_eq!(
+            voice("K:C\nL:1/4\nC, C c c'|"),
+            [Note(1, -1), Note(1, 0), Note(1, 1), Note(1, 2), Bar]
+        );
+    }
+
+    #[test]
+    fn written_tunes_read_back_the_same() {
+        let text = "X:1\nT:Drowsy Maggie\nM:3/4\nL:1/8\nQ:1/4=140\nK:Ador\n\
+                    V:1\n|:A>B c (3ded|1e2 A4:|2e2 ^g4|]\nV:2\n|:A,4 E2|1A,6:|2E,6|]\n";
+        let tune = parse_abc(text).unwrap();
+        let again = parse_abc(&format_abc(&tune).unwrap()).unwrap();
+        assert_eq!(again.title.as_deref(), Some("Drowsy Maggie"));
+        assert_eq!(again.bpm, Some(140));
+        assert_eq!(again.scale_intervals, tune.scale_intervals);
+        assert_eq!(again.symbol_ticks, tune.symbol_ticks);
+        assert_eq!(again.voices.len(), 2);
+        // The bar line of the opening |: is not written out
+        for (written, read) in tune.voices.iter().zip(&again.voices) {
+            assert_eq!(&written[1..], read);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_tunes() {
+        for text in [
+            "X:1\nK:C\n",
+            "X:1\nK:C\nZ0",
+            "X:1\nK:C\nX0|",
+            "X:1\nK:C\nC0 D|",
+            "X:1\nK:C\nz0|",
+            "X:1\nK:C\n[CE]0|",
+            "X:1\nK:C\nZ99999999999999999999",
+            "X:1\nK:C\nC>>>>D|",
+        ] {
+            assert!(parse_abc(text).is_err(), "{:?}", text);
+        }
+        // Too long a run to shift by
+        assert!(parse_abc(&format!("X:1\nK:C\nC{}D|", "<".repeat(64))).is_err());
+    }
+}
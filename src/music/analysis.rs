@@ -0,0 +1,141 @@
+//! Key and scale detection from pitch-class content
+
+use super::melody::NoteElement;
+use super::note::Note;
+use super::scale::ScaleRegistry;
+
+/// How much of a melody falls on each pitch class (C = 0 ... B = 11),
+/// weighted by note length, and which pitch classes it starts and ends on
+#[derive(Debug, Clone, Default)]
+pub struct PitchProfile {
+    pub weights: [f32; 12],
+    pub first: Option<usize>,
+    pub last: Option<usize>,
+}
+
+impl PitchProfile {
+    /// Pitch content of scale-degree notation played in a scale from `root`;
+    /// each note counts once plus once per sustain
+    pub fn from_elements(elements: &[NoteElement], scale_intervals: &[i32], root: Note) -> Self {
+        let mut profile = Self::default();
+        let mut current = None;
+        for element in elements {
+            match element {
+                NoteElement::Note(position, _) => {
+                    current = position
+                        .checked_sub(1)
+                        .and_then(|index| scale_intervals.get(index))
+                        .map(|interval| root.to_semitone() + interval);
+                    if let Some(semitone) = current {
+                        profile.add(semitone, 1.0);
+                    }
+                }
+                NoteElement::Sustain => {
+                    if let Some(semitone) = current {
+                        profile.add(semitone, 1.0);
+                    }
+                }
+                NoteElement::Rest => current = None,
+                NoteElement::Bar
+                | NoteElement::Tie
+                | NoteElement::Staccato
+                | NoteElement::Legato => {}
+            }
+        }
+        profile
+    }
+
+    /// Add notes in the order they are played
+    pub fn add(&mut self, semitone: i32, weight: f32) {
+        let pitch_class = semitone.rem_euclid(12) as usize;
+        self.weights[pitch_class] += weight;
+        self.first.get_or_insert(pitch_class);
+        self.last = Some(pitch_class);
+    }
+
+    pub fn total(&self) -> f32 {
+        self.weights.iter().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total() <= 0.0
+    }
+
+    /// Pitch classes that occur, with their share of the total
+    pub fn used(&self) -> Vec<(Note, f32)> {
+        let total = self.total();
+        (0..12)
+            .filter(|&pc| self.weights[pc] > 0.0)
+            .map(|pc| (Note::from_semitone(pc as i32), self.weights[pc] / total))
+            .collect()
+    }
+}
+
+/// One key and scale, and how well the melody fits it
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub root: Note,
+    /// Registry id of the scale
+    pub scale_id: String,
+    pub scale_name: String,
+    /// Share of the melody (by weight) that lies in the scale, 0-1
+    pub fit: f32,
+    /// Ranking score: fit, plus a little for weight on the root and fifth
+    /// and for starting or ending on the root, minus a little for each scale
+    /// note the melody never uses
+    pub score: f32,
+    /// Notes played that are not in the scale
+    pub outside: Vec<Note>,
+}
+
+/// Score every root and scale in the registry, best first
+pub fn analyze(profile: &PitchProfile, registry: &ScaleRegistry) -> Vec<Candidate> {
+    let total = profile.total();
+    if total <= 0.0 {
+        return Vec::new();
+    }
+
+    let mut candidates = Vec::new();
+    for root in 0..12 {
+        for entry in registry.iter() {
+            let mut in_scale = [false; 12];
+            for &interval in &entry.scale.intervals {
+                in_scale[(root + interval).rem_euclid(12) as usize] = true;
+            }
+
+            let inside: f32 = (0..12)
+                .filter(|&pc| in_scale[pc])
+                .map(|pc| profile.weights[pc])
+                .sum();
+            let fit = inside / total;
+            let mut tonic = (profile.weights[root as usize]
+                + 0.5 * profile.weights[(root + 7) as usize % 12])
+                / total;
+            // Melodies tend to start and end on the tonic
+            if profile.first == Some(root as usize) {
+                tonic += 0.4;
+            }
+            if profile.last == Some(root as usize) {
+                tonic += 0.3;
+            }
+            let unused = (0..12)
+                .filter(|&pc| in_scale[pc] && profile.weights[pc] == 0.0)
+                .count();
+
+            candidates.push(Candidate {
+                root: Note::from_semitone(root),
+                scale_id: entry.id.clone(),
+                scale_name: entry.scale.name.clone(),
+                fit,
+                score: fit + 0.1 * tonic - 0.03 * unused as f32,
+                outside: (0..12)
+                    .filter(|&pc| !in_scale[pc] && profile.weights[pc] > 0.0)
+                    .map(|pc| Note::from_semitone(pc as i32))
+                    .collect(),
+            });
+        }
+    }
+
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    candidates
+}
@@ -1,28 +1,67 @@
-//! Duration utilities for musical timing
+//! Musical time in ticks
+//!
+//! Note lengths are counted in ticks, a fixed fraction of a quarter note, so
+//! they stay exact whatever the tempo. Ticks become samples only when a
+//! melody is rendered, and each note's start is converted from the running
+//! tick count so rounding never accumulates over a long piece.
 
 use std::time::Duration;
 
+/// Musical time in ticks of [`PPQ`] per quarter note
+pub type Ticks = u64;
+
+/// Ticks per quarter note; divisible by 2, 3, 4, 5 and 8, so triplets,
+/// quintuplets and notes down to 1/256 are whole numbers of ticks
+pub const PPQ: Ticks = 960;
+
 /// Helper functions to create common note durations
+#[allow(clippy::module_inception)]
 pub mod duration {
-    use super::Duration;
+    use super::{Ticks, PPQ};
+
+    pub fn whole_note() -> Ticks {
+        PPQ * 4
+    }
 
-    pub fn whole_note() -> Duration {
-        Duration::from_millis(1000)
+    pub fn half_note() -> Ticks {
+        PPQ * 2
     }
 
-    pub fn half_note() -> Duration {
-        Duration::from_millis(500)
+    pub fn quarter_note() -> Ticks {
+        PPQ
     }
 
-    pub fn quarter_note() -> Duration {
-        Duration::from_millis(250)
+    pub fn eighth_note() -> Ticks {
+        PPQ / 2
     }
 
-    pub fn eighth_note() -> Duration {
-        Duration::from_millis(125)
+    pub fn sixteenth_note() -> Ticks {
+        PPQ / 4
     }
+}
 
-    pub fn sixteenth_note() -> Duration {
-        Duration::from_millis(63)
+/// Length of a note value by name: whole/1, half/2, quarter/4, eighth/8 or
+/// sixteenth/16
+pub fn note_value(name: &str) -> Option<Ticks> {
+    match name.to_lowercase().as_str() {
+        "whole" | "1" => Some(duration::whole_note()),
+        "half" | "2" => Some(duration::half_note()),
+        "quarter" | "4" => Some(duration::quarter_note()),
+        "eighth" | "8" => Some(duration::eighth_note()),
+        "sixteenth" | "16" => Some(duration::sixteenth_note()),
+        _ => None,
     }
-}
\ No newline at end of file
+}
+
+/// Sample position of a tick position at `bpm` (quarter note = 1 beat),
+/// rounded to the nearest sample
+pub fn ticks_to_samples(ticks: Ticks, bpm: u32, sample_rate: u32) -> u64 {
+    let per_minute = PPQ * bpm.max(1) as u64;
+    (ticks * 60 * sample_rate as u64 + per_minute / 2) / per_minute
+}
+
+/// Wall-clock length of `ticks` at `bpm`
+pub fn ticks_to_duration(ticks: Ticks, bpm: u32) -> Duration {
+    let per_minute = PPQ * bpm.max(1) as u64;
+    Duration::from_nanos(ticks * 60_000_000_000 / per_minute)
+}
@@ -1,6 +1,6 @@
 //! Musical interval constants and patterns
 
-/// Common musical intervals (in semitones from root)
+// Common musical intervals (in semitones from root)
 
 pub const ROOT: i32 = 0; // Root note (unison)
 pub const MINOR_SECOND: i32 = 1;
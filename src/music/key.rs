@@ -1,8 +1,10 @@
 //! Musical key system for interval-based composition
 
 use super::note::Note;
+use crate::error::SoundError;
 
 /// Represents a musical key with a root note and octave
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Copy)]
 pub struct Key {
     pub root: Note,
@@ -14,32 +16,42 @@ impl Key {
         Self { root, octave }
     }
 
-    /// Get a note by interval from the root (0 = root, 1 = one semitone up, etc.)
-    /// Now properly handles negative intervals for lower octaves
-    pub fn note_at_interval(&self, interval: i32) -> (Note, u8) {
-        let root_semitone = self.root.to_semitone() as i32;
-        let target_semitone = root_semitone + interval;
-
-        // Calculate octave changes (handle negative properly)
-        let octave_change = if target_semitone >= 0 {
-            target_semitone / 12
-        } else {
-            // For negative semitones, we need to go down octaves
-            (target_semitone - 11) / 12 // This ensures proper floor division
-        };
-
-        // Calculate final octave, but don't let it go below 0
-        let final_octave = ((self.octave as i32 + octave_change).max(0)) as u8;
-
-        // Calculate the note (handle negative modulo properly)
-        let note_semitone = if target_semitone >= 0 {
-            target_semitone % 12
-        } else {
-            ((target_semitone % 12) + 12) % 12 // Positive modulo for negative numbers
-        };
+    /// Move the key up (or down, for negative `octaves`) by whole octaves
+    pub fn shift_octaves(&self, octaves: i32) -> Result<Self, SoundError> {
+        Ok(Self::new(
+            self.root,
+            checked_octave(self.octave as i32 + octaves)?,
+        ))
+    }
 
-        let final_note = Note::from_semitone(note_semitone);
+    /// Get a note by interval from the root (0 = root, 1 = one semitone up, etc.)
+    /// Negative intervals reach into lower octaves; notes outside octaves
+    /// 0-8 are an error rather than being clamped
+    pub fn note_at_interval(&self, interval: i32) -> Result<(Note, u8), SoundError> {
+        let target_semitone = self.root.to_semitone() + interval;
+        let octave =
+            checked_octave(self.octave as i32 + target_semitone.div_euclid(12)).map_err(|_| {
+                SoundError::OutOfRange(format!(
+                    "{} semitones from {}{} is out of range (octaves {}-{})",
+                    interval, self.root, self.octave, MIN_OCTAVE, MAX_OCTAVE
+                ))
+            })?;
+        Ok((Note::from_semitone(target_semitone.rem_euclid(12)), octave))
+    }
+}
 
-        (final_note, final_octave)
+/// Lowest and highest octaves notes can be played in
+pub const MIN_OCTAVE: u8 = 0;
+pub const MAX_OCTAVE: u8 = 8;
+
+/// An octave number, if it is in range
+pub fn checked_octave(octave: i32) -> Result<u8, SoundError> {
+    if (MIN_OCTAVE as i32..=MAX_OCTAVE as i32).contains(&octave) {
+        Ok(octave as u8)
+    } else {
+        Err(SoundError::OutOfRange(format!(
+            "Octave {} is out of range ({}-{})",
+            octave, MIN_OCTAVE, MAX_OCTAVE
+        )))
     }
 }
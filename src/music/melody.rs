@@ -1,143 +1,198 @@
 //! Melody composition and playback
 
-use crate::{music::interval, Key, MusicNote, Note, Square};
-use rodio::Sink;
+use crate::audio::{instrument::Instrument, wave::WaveType, SAMPLE_RATE};
+use crate::error::SoundError;
+use crate::music::duration::{
+    duration::sixteenth_note, note_value, ticks_to_duration, ticks_to_samples, Ticks,
+};
+use crate::music::scale::Scale;
+use crate::{music::interval, Key, MusicNote, Note};
+use rodio::{source::from_iter, Sink, Source};
 use std::time::Duration;
 
-/// Melody composer for playing sequences of notes
+/// Melody composer: notes placed in time, in a key and scale
+///
+/// Each note added starts where the previous one ended. Chords and overlaid
+/// melodies put several notes at the same time. Notes that cannot be played
+/// (a degree the scale lacks, a pitch beyond the octave range) become rests
+/// and are listed by [`Melody::errors`], so adding notes never fails.
 pub struct Melody {
-    notes: Vec<MusicNote>,
+    /// Notes that sound, with their start, ordered by start
+    notes: Vec<(Ticks, MusicNote)>,
+    /// Where the next note goes, which is also the melody's length
+    position: Ticks,
+    /// Problems with notes that were replaced by rests
+    errors: Vec<SoundError>,
     key: Key,
     sample_rate: u32,
+    /// Share of its length each note sounds, from the builder
+    gate: f32,
     pub bpm: u32,
-    pub base_note_duration: Duration,
-    sixteenth_note_duration: Duration,
     pub scale_name: String,
-    pub scale_intervals: &'static [i32],
-    pub note_elements: Vec<NoteElement>,
+    pub scale_intervals: Vec<i32>,
     pub should_loop: bool,
-    pub base_duration: String,
+    pub instrument: Instrument,
 }
 
 impl Melody {
-    pub fn new(config: MelodyConfig) -> Self {
-        let mut melody = Self {
-            notes: Vec::new(),
-            key: config.key,
-            sample_rate: config.sample_rate,
-            bpm: config.bpm,
-            base_duration: config.base_duration,
-            scale_name: config.scale_name,
-            scale_intervals: config.scale_intervals,
-            note_elements: config.note_elements,
-            should_loop: config.should_loop,
-            base_note_duration: Duration::from_millis(0),
-            sixteenth_note_duration: Duration::from_millis(0),
-        };
+    /// Start composing with C4 major at 120 BPM on a square wave
+    pub fn builder() -> MelodyBuilder {
+        MelodyBuilder::default()
+    }
 
-        let (base_note_duration, sixteenth_note_duration) =
-            Melody::calculate_durations(melody.bpm, &melody.base_duration);
-        melody.base_note_duration = base_note_duration;
-        melody.sixteenth_note_duration = sixteenth_note_duration;
+    /// An empty melody in `key`, with the builder's other defaults
+    pub fn in_key(key: Key) -> Self {
+        Self::builder().key(key).build()
+    }
 
-        // Build the melody once
-        // let mut melody = self;
+    /// A melody from parsed notation and CLI-style settings
+    pub fn new(config: MelodyConfig) -> Self {
+        // Unknown names fall back to sixteenth notes
+        let symbol_ticks = note_value(&config.base_duration).unwrap_or_else(sixteenth_note);
+        Self::builder()
+            .key(config.key)
+            .scale(Scale {
+                name: config.scale_name,
+                intervals: config.scale_intervals,
+            })
+            .tempo(config.bpm)
+            .sample_rate(config.sample_rate)
+            .looping(config.should_loop)
+            .instrument(config.instrument)
+            .gate(config.gate)
+            .build()
+            .add_elements(&config.note_elements, symbol_ticks)
+    }
+
+    /// Add notes written in notation, each symbol `symbol_ticks` long.
+    ///
+    /// A sustain makes the note before it one symbol longer, across bar
+    /// lines and octave marks, and a tie adds the next note to it when that
+    /// note has the same pitch. Staccato notes sound for half their length
+    /// and legato notes for all of it, whatever the melody's gate.
+    pub fn add_elements(mut self, elements: &[NoteElement], symbol_ticks: Ticks) -> Self {
         let mut i: usize = 0;
 
-        while i < melody.note_elements.len() {
-            match &melody.note_elements[i] {
+        while i < elements.len() {
+            match &elements[i] {
                 NoteElement::Note(position, octave_offset) => {
-                    if *position == 0 || *position > melody.scale_intervals.len() {
-                        println!(
-                            "⚠️  Warning: Note position {} is out of range for this scale",
-                            position
-                        );
-                        i += 1;
-                        continue;
+                    // An unplayable degree is reported and kept as a rest of the same length
+                    let interval = self.degree_interval(*position, *octave_offset);
+                    if interval.is_none() {
+                        self.errors.push(position_error(*position));
                     }
-                    // Calculate interval with octave offset (12 semitones per octave)
-                    let base_interval = config.scale_intervals[position - 1];
-                    let interval = base_interval + (octave_offset * 12);
-
-                    // Count sustains that follow this note
-                    let mut sustain_count = 0;
-                    let mut j = i + 1;
-                    while j < melody.note_elements.len() {
-                        match &melody.note_elements[j] {
-                            NoteElement::Sustain => {
-                                sustain_count += 1;
-                                j += 1;
+
+                    // Sustains, ties and marks that belong to this note
+                    let mut ticks = symbol_ticks;
+                    let mut gate = self.gate;
+                    i += 1;
+                    while let Some(element) = elements.get(i) {
+                        match element {
+                            NoteElement::Sustain => ticks += symbol_ticks,
+                            NoteElement::Staccato => gate = STACCATO,
+                            NoteElement::Legato => gate = 1.0,
+                            NoteElement::Bar => {}
+                            NoteElement::Tie => {
+                                let next = elements[i + 1..]
+                                    .iter()
+                                    .position(|element| !matches!(element, NoteElement::Bar))
+                                    .map(|offset| i + 1 + offset);
+                                if let Some(next) = next {
+                                    if let NoteElement::Note(position, octave) = elements[next] {
+                                        // A rest has nothing to tie to the next note
+                                        if interval.is_none() {
+                                            break;
+                                        }
+                                        if self.degree_interval(position, octave) != interval {
+                                            self.errors.push(tie_error());
+                                            break;
+                                        }
+                                        ticks += symbol_ticks;
+                                        i = next;
+                                    }
+                                }
                             }
-                            _ => break,
+                            NoteElement::Note(..) | NoteElement::Rest => break,
                         }
+                        i += 1;
                     }
-
-                    // Calculate duration: configurable base duration + sustains (sixteenth notes)
-                    let base_duration = base_note_duration;
-                    let sustain_duration = Duration::from_millis(
-                        sixteenth_note_duration.as_millis() as u64 * sustain_count as u64,
-                    );
-                    let total_duration = base_duration + sustain_duration;
-
-                    melody = melody.add_interval(interval, total_duration);
-
-                    // Skip past the sustains we just processed
-                    i = j;
+                    self = match interval {
+                        Some(interval) => self.add_interval_gated(interval, ticks, gate),
+                        None => self.add_rest(ticks),
+                    };
                 }
-                NoteElement::Rest => {
-                    melody = melody.add_rest(base_note_duration);
-                    i += 1;
-                }
-                NoteElement::Sustain => {
+                NoteElement::Rest | NoteElement::Sustain => {
                     // Sustains without a preceding note are treated as rests
-                    melody = melody.add_rest(base_note_duration);
+                    self = self.add_rest(symbol_ticks);
                     i += 1;
                 }
+                // Marks with no note before them take no time
+                NoteElement::Bar
+                | NoteElement::Tie
+                | NoteElement::Staccato
+                | NoteElement::Legato => i += 1,
             }
         }
-        melody
+        self
     }
 
-    // /// Create melody in a specific key
-    // pub fn in_key(key: Key) -> Self {
-    //     Self {
-    //         notes: Vec::new(),
-    //         key: Some(key),
-    //     }
-    // }
-
     /// Add note by absolute note and octave (original method)
-    pub fn add_note(mut self, note: Note, octave: u8, duration: Duration) -> Self {
-        self.notes.push(MusicNote::new(note, octave, duration));
-        self
+    pub fn add_note(self, note: Note, octave: u8, ticks: Ticks) -> Self {
+        self.push(MusicNote::new(note, octave, ticks))
     }
 
     /// Add note by interval from the key's root (0 = root, 1 = one semitone up, etc.)
-    pub fn add_interval(mut self, interval: i32, duration: Duration) -> Self {
-        // if let Some(key) = self.key {
-        //     self.notes
-        //         .push(MusicNote::from_key_interval(&key, interval, duration));
-        // } else {
-        //     panic!(
-        //         "Cannot add interval without setting a key first. Use Melody::in_key() or add_note() instead."
-        //     );
-        // }
-        self.notes
-            .push(MusicNote::from_key_interval(&self.key, interval, duration));
+    pub fn add_interval(self, interval: i32, ticks: Ticks) -> Self {
+        let gate = self.gate;
+        self.add_interval_gated(interval, ticks, gate)
+    }
+
+    /// Add a note by interval that sounds for `gate` (0 to 1) of its length,
+    /// with silence for the rest: 0.5 for staccato, 1.0 for legato
+    pub fn add_interval_gated(mut self, interval: i32, ticks: Ticks, gate: f32) -> Self {
+        match MusicNote::from_key_interval(&self.key, interval, ticks) {
+            Ok(note) => self.push_gated(note, gate),
+            Err(error) => {
+                // Keep the timing: an unplayable note becomes a rest
+                self.errors.push(error);
+                self.add_rest(ticks)
+            }
+        }
+    }
+
+    /// Add one note per interval, one after another
+    pub fn add_intervals(mut self, intervals: &[i32], ticks: Ticks) -> Self {
+        for &interval in intervals {
+            self = self.add_interval(interval, ticks);
+        }
         self
     }
 
-    /// Add multiple intervals at once
-    // pub fn add_intervals(mut self, intervals: &[i32], duration: Duration) -> Self {
-    //     for &interval in intervals {
-    //         self = self.add_interval(interval, duration);
-    //     }
-    //     self
-    // }
+    /// Add a note by scale degree (1 = root) and octave, as in notation
+    pub fn add_degree(mut self, degree: usize, octave: i32, ticks: Ticks) -> Self {
+        match self.degree_interval(degree, octave) {
+            Some(interval) => self.add_interval(interval, ticks),
+            None => {
+                self.errors.push(position_error(degree));
+                self.add_rest(ticks)
+            }
+        }
+    }
+
+    /// Add notes that sound together, as intervals from the key's root
+    pub fn add_chord(mut self, intervals: &[i32], ticks: Ticks) -> Self {
+        let start = self.position;
+        for &interval in intervals {
+            self.position = start;
+            self = self.add_interval(interval, ticks);
+        }
+        self.position = start + ticks;
+        self
+    }
 
     /// Add a rest (silent note)
-    pub fn add_rest(mut self, duration: Duration) -> Self {
-        self.notes.push(MusicNote::new(Note::Rest, 0, duration));
+    pub fn add_rest(mut self, ticks: Ticks) -> Self {
+        self.position += ticks;
         self
     }
 
@@ -147,70 +202,314 @@ impl Melody {
         self
     }
 
+    /// Play `other` after this melody. Its notes keep their pitches; the
+    /// tempo and instrument are this melody's.
+    pub fn append(mut self, other: Melody) -> Self {
+        let offset = self.position;
+        self.notes.extend(
+            other
+                .notes
+                .into_iter()
+                .map(|(start, note)| (start + offset, note)),
+        );
+        self.errors.extend(other.errors);
+        self.position += other.position;
+        self
+    }
+
+    /// Play `other` at the same time as this melody, from the start
+    pub fn overlay(mut self, other: Melody) -> Self {
+        self.notes.extend(other.notes);
+        self.notes.sort_by_key(|(start, _)| *start);
+        self.errors.extend(other.errors);
+        self.position = self.position.max(other.position);
+        self
+    }
+
+    pub fn key(&self) -> Key {
+        self.key
+    }
+
+    /// Notes that could not be played and were replaced by rests
+    pub fn errors(&self) -> &[SoundError] {
+        &self.errors
+    }
+
+    /// Notes that sound, each with its start in ticks
+    pub fn notes(&self) -> &[(Ticks, MusicNote)] {
+        &self.notes
+    }
+
     /// Play the melody using the provided sink
     pub fn play(&self, sink: &Sink) {
-        for note in &self.notes {
-            let square_wave = Square::from_note(note, self.sample_rate);
-            sink.append(square_wave);
+        sink.append(self.source());
+    }
+
+    /// The whole melody through its instrument as a single finite source,
+    /// for mixing or rendering
+    pub fn source(&self) -> impl Source + Send + 'static {
+        // Each note runs between sample positions rounded from exact tick
+        // positions, so timing never drifts
+        let samples = |ticks| ticks_to_samples(ticks, self.bpm, self.sample_rate) as usize;
+        let silence = |length| {
+            self.instrument
+                .note(&MusicNote::new(Note::Rest, 0, 0), self.sample_rate, length)
+        };
+
+        // Overlapping notes go in separate lanes, each a plain sequence
+        let mut lanes: Vec<(Ticks, Vec<Box<dyn Source + Send>>)> = vec![(0, Vec::new())];
+        for (start, note) in &self.notes {
+            let lane = match lanes.iter().position(|(end, _)| end <= start) {
+                Some(lane) => lane,
+                None => {
+                    lanes.push((0, Vec::new()));
+                    lanes.len() - 1
+                }
+            };
+            let (end, sources) = &mut lanes[lane];
+            if *end < *start {
+                sources.push(silence(samples(*start) - samples(*end)));
+            }
+            *end = start + note.ticks;
+            sources.push(self.instrument.note(
+                note,
+                self.sample_rate,
+                samples(*end) - samples(*start),
+            ));
+        }
+        // The first lane carries trailing rests, so the melody lasts its full length
+        let (end, sources) = &mut lanes[0];
+        if *end < self.position {
+            sources.push(silence(samples(self.position) - samples(*end)));
         }
+
+        let mixed = lanes
+            .into_iter()
+            .map(|(_, sources)| Box::new(from_iter(sources)) as Box<dyn Source + Send>)
+            .reduce(|mixed, lane| Box::new(mixed.mix(lane)))
+            .unwrap();
+        self.instrument.effects.apply(self.instrument.voice(mixed))
+    }
+
+    /// Total length in ticks
+    pub fn ticks(&self) -> Ticks {
+        self.position
     }
 
-    fn calculate_durations(bpm: u32, base_duration: &str) -> (Duration, Duration) {
-        let quarter_note_ms = 60_000 / bpm; // milliseconds per quarter note
-        let sixteenth_note_ms = quarter_note_ms / 4; // sixteenth note for sustains
+    /// How long one pass through the melody takes at its tempo
+    pub fn length(&self) -> Duration {
+        ticks_to_duration(self.ticks(), self.bpm)
+    }
 
-        // Calculate base duration based on the specified type
-        let base_duration_ms = match base_duration.to_lowercase().as_str() {
-            "whole" | "1" => quarter_note_ms * 4,
-            "half" | "2" => quarter_note_ms * 2,
-            "quarter" | "4" => quarter_note_ms,
-            "eighth" | "8" => quarter_note_ms / 2,
-            "sixteenth" | "16" => quarter_note_ms / 4,
-            _ => quarter_note_ms / 4, // fallback to sixteenth
-        };
+    fn push(self, note: MusicNote) -> Self {
+        let gate = self.gate;
+        self.push_gated(note, gate)
+    }
 
-        (
-            Duration::from_millis(base_duration_ms as u64), // configurable base duration
-            Duration::from_millis(sixteenth_note_ms as u64), // sixteenth note for sustains
-        )
+    /// Place a note that sounds for `gate` of its length; the next note still
+    /// starts after all of it. A note with no length is left out.
+    fn push_gated(mut self, mut note: MusicNote, gate: f32) -> Self {
+        let ticks = note.ticks;
+        if ticks > 0 && !matches!(note.note, Note::Rest) {
+            note.ticks = ((ticks as f32 * gate.clamp(0.0, 1.0)).round() as Ticks).clamp(1, ticks);
+            self.notes.push((self.position, note));
+        }
+        self.position += ticks;
+        self
+    }
+
+    /// Semitones from the root of a scale position (1-based) in an octave
+    fn degree_interval(&self, degree: usize, octave: i32) -> Option<i32> {
+        let interval = degree
+            .checked_sub(1)
+            .and_then(|index| self.scale_intervals.get(index))?;
+        Some(interval + octave * 12)
     }
 }
 
-// impl Default for Melody {
-//     fn default() -> Self {
-//         Self::new()
-//     }
-// }
+/// Share of its length a staccato note sounds
+const STACCATO: f32 = 0.5;
 
-/// Represents different musical elements in our enhanced notation
+fn tie_error() -> SoundError {
+    SoundError::Invalid(
+        "A tie (~) joins notes of the same pitch; playing them separately".to_string(),
+    )
+}
+
+fn position_error(position: usize) -> SoundError {
+    SoundError::OutOfRange(format!(
+        "Note position {} is out of range for this scale",
+        position
+    ))
+}
+
+/// Settings for a new [`Melody`]
+///
+/// ```
+/// use sound::{duration, get_scale_by_name, Key, Melody, Note};
+///
+/// let melody = Melody::builder()
+///     .key(Key::new(Note::D, 4))
+///     .scale(get_scale_by_name("dorian").unwrap())
+///     .tempo(96)
+///     .build()
+///     .add_degree(1, 0, duration::quarter_note())
+///     .add_degree(3, 0, duration::quarter_note())
+///     .add_chord(&[0, 3, 7], duration::half_note());
+/// assert_eq!(melody.ticks(), duration::whole_note());
+/// ```
 #[derive(Debug, Clone)]
+pub struct MelodyBuilder {
+    key: Key,
+    scale: Scale,
+    bpm: u32,
+    sample_rate: u32,
+    should_loop: bool,
+    instrument: Instrument,
+    gate: f32,
+}
+
+impl Default for MelodyBuilder {
+    fn default() -> Self {
+        Self {
+            key: Key::new(Note::C, 4),
+            scale: Scale {
+                name: "Major".to_string(),
+                intervals: interval::MAJOR_SCALE.to_vec(),
+            },
+            bpm: 120,
+            sample_rate: SAMPLE_RATE,
+            should_loop: false,
+            instrument: Instrument::from_wave(WaveType::Square),
+            gate: 1.0,
+        }
+    }
+}
+
+impl MelodyBuilder {
+    pub fn key(mut self, key: Key) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Scale that degrees are counted in
+    pub fn scale(mut self, scale: Scale) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Beats (quarter notes) per minute
+    pub fn tempo(mut self, bpm: u32) -> Self {
+        self.bpm = bpm;
+        self
+    }
+
+    pub fn sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn looping(mut self, should_loop: bool) -> Self {
+        self.should_loop = should_loop;
+        self
+    }
+
+    pub fn instrument(mut self, instrument: Instrument) -> Self {
+        self.instrument = instrument;
+        self
+    }
+
+    /// Share of its length each note sounds, from just above 0 (very
+    /// short) to 1 (the whole length, the default)
+    pub fn gate(mut self, gate: f32) -> Self {
+        self.gate = gate;
+        self
+    }
+
+    pub fn build(self) -> Melody {
+        Melody {
+            notes: Vec::new(),
+            position: 0,
+            errors: Vec::new(),
+            key: self.key,
+            sample_rate: self.sample_rate,
+            gate: self.gate,
+            bpm: self.bpm,
+            scale_name: self.scale.name,
+            scale_intervals: self.scale.intervals,
+            should_loop: self.should_loop,
+            instrument: self.instrument,
+        }
+    }
+}
+
+/// Represents different musical elements in our enhanced notation
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
 pub enum NoteElement {
     /// A note at a specific scale position with octave offset
     Note(usize, i32), // (scale_position, octave_offset)
-    /// A sixteenth-note rest
+    /// A rest one symbol long
     Rest,
-    /// A sixteenth-note sustain (extends the previous note)
+    /// A sustain, making the previous note one symbol longer
     Sustain,
+    /// A bar line, which takes no time
+    Bar,
+    /// A tie, joining the previous note to the next one of the same pitch
+    Tie,
+    /// Play the previous note short (half its length)
+    Staccato,
+    /// Play the previous note for its whole length
+    Legato,
+}
+
+impl NoteElement {
+    /// Whether the element lasts a symbol: notes, rests and sustains do,
+    /// bar lines, ties and articulation marks take no time
+    pub fn has_length(&self) -> bool {
+        matches!(
+            self,
+            NoteElement::Note(..) | NoteElement::Rest | NoteElement::Sustain
+        )
+    }
 }
 
 // Configuration struct for melody generation
-#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
 pub struct MelodyConfig {
     pub scale_name: String,
-    pub scale_intervals: &'static [i32],
+    pub scale_intervals: Vec<i32>,
     pub note_elements: Vec<NoteElement>,
     pub key: Key,
     pub bpm: u32,
     pub should_loop: bool,
     pub base_duration: String,
+    /// Share of its length each note sounds (1.0 for the whole length)
+    #[cfg_attr(feature = "serde", serde(default = "default_gate"))]
+    pub gate: f32,
     pub sample_rate: u32,
+    /// Instruments hold live effect chains, so they are left out of
+    /// serialized configs and come back as the default square wave
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_instrument"))]
+    pub instrument: Instrument,
+}
+
+#[cfg(feature = "serde")]
+fn default_instrument() -> Instrument {
+    Instrument::from_wave(WaveType::Square)
+}
+
+#[cfg(feature = "serde")]
+fn default_gate() -> f32 {
+    1.0
 }
 
 impl Default for MelodyConfig {
     fn default() -> Self {
         Self {
             scale_name: "major".to_string(),
-            scale_intervals: &interval::MAJOR_SCALE,
+            scale_intervals: interval::MAJOR_SCALE.to_vec(),
             note_elements: vec![
                 NoteElement::Note(1, 0),
                 NoteElement::Note(2, 0),
@@ -225,7 +524,52 @@ impl Default for MelodyConfig {
             bpm: 120,
             should_loop: false,
             base_duration: "sixteenth".to_string(),
-            sample_rate: 44100,
+            gate: 1.0,
+            sample_rate: SAMPLE_RATE,
+            instrument: Instrument::from_wave(WaveType::Square),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unplayable_degree_keeps_its_length() {
+        let elements = [
+            NoteElement::Note(9, 0),
+            NoteElement::Sustain,
+            NoteElement::Note(1, 0),
+        ];
+        let melody = Melody::builder().build().add_elements(&elements, 240);
+        assert_eq!(melody.errors().len(), 1);
+        assert_eq!(melody.notes().len(), 1);
+        assert_eq!(melody.notes()[0].0, 480);
+        assert_eq!(melody.ticks(), 720);
+    }
+
+    #[test]
+    fn zero_length_notes_are_left_out() {
+        let melody = Melody::builder()
+            .build()
+            .add_degree(1, 0, 0)
+            .add_note(Note::C, 4, 0);
+        assert!(melody.notes().is_empty());
+        assert_eq!(melody.ticks(), 0);
+    }
+
+    #[test]
+    fn gate_shortens_notes_but_not_their_place() {
+        let melody = Melody::builder()
+            .gate(0.5)
+            .build()
+            .add_interval(0, 240)
+            .add_interval_gated(2, 3, 0.1);
+        assert_eq!(melody.notes()[0].1.ticks, 120);
+        // Every sounding note keeps at least one tick
+        assert_eq!(melody.notes()[1].0, 240);
+        assert_eq!(melody.notes()[1].1.ticks, 1);
+        assert_eq!(melody.ticks(), 243);
+    }
+}
@@ -0,0 +1,67 @@
+//! Time signatures, for checking bar lines in notation
+
+use super::duration::{duration::whole_note, Ticks};
+use crate::error::SoundError;
+use std::fmt;
+
+/// A time signature such as 4/4 or 6/8
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Meter {
+    /// Beats per bar, the upper number
+    pub beats: u32,
+    /// Note value of one beat, the lower number (4 = quarter note)
+    pub unit: u32,
+}
+
+impl Meter {
+    pub fn new(beats: u32, unit: u32) -> Self {
+        Self { beats, unit }
+    }
+
+    /// Read a time signature written as `beats/unit`, e.g. `3/4` or `6/8`
+    pub fn parse(text: &str) -> Result<Self, SoundError> {
+        let invalid = || {
+            SoundError::Invalid(format!(
+                "Invalid meter '{}'. Use beats/unit, e.g. 4/4, 3/4 or 6/8",
+                text
+            ))
+        };
+        let (beats, unit) = text.trim().split_once('/').ok_or_else(invalid)?;
+        let beats: u32 = beats.trim().parse().map_err(|_| invalid())?;
+        let unit: u32 = unit.trim().parse().map_err(|_| invalid())?;
+        if beats == 0 || !matches!(unit, 1 | 2 | 4 | 8 | 16 | 32) {
+            return Err(invalid());
+        }
+        Ok(Self::new(beats, unit))
+    }
+
+    /// Length of one beat
+    pub fn beat_ticks(&self) -> Ticks {
+        whole_note() / self.unit as Ticks
+    }
+
+    /// Length of a full bar
+    pub fn bar_ticks(&self) -> Ticks {
+        self.beat_ticks() * self.beats as Ticks
+    }
+
+    /// `ticks` counted in beats, e.g. "1 beat" or "3.5 beats"
+    pub fn format_beats(&self, ticks: Ticks) -> String {
+        let beats = format!("{:.2}", ticks as f64 / self.beat_ticks() as f64);
+        let beats = beats.trim_end_matches('0').trim_end_matches('.');
+        format!("{} beat{}", beats, if beats == "1" { "" } else { "s" })
+    }
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Self::new(4, 4)
+    }
+}
+
+impl fmt::Display for Meter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.beats, self.unit)
+    }
+}
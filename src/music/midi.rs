@@ -0,0 +1,217 @@
+//! Minimal Standard MIDI File reader
+//!
+//! Reads format 0 and 1 files and keeps only what the analysis needs: each
+//! note's key, channel, start and length in ticks. Tempo, controllers and
+//! other events are skipped.
+
+use crate::error::SoundError;
+use std::fs;
+use std::path::Path;
+
+/// A note from a MIDI file, timed in ticks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MidiNote {
+    /// MIDI key number (C4 = 60)
+    pub key: u8,
+    /// Channel 0-15 (9 is General MIDI percussion)
+    pub channel: u8,
+    pub start: u64,
+    pub length: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MidiFile {
+    /// Ticks per quarter note, or 0 for SMPTE-timed files
+    pub ticks_per_quarter: u16,
+    pub notes: Vec<MidiNote>,
+}
+
+impl MidiFile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SoundError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|error| SoundError::Io {
+            path: path.display().to_string(),
+            message: format!("cannot read: {}", error),
+        })?;
+        Self::parse(&bytes).map_err(|error| SoundError::Io {
+            path: path.display().to_string(),
+            message: error.to_string(),
+        })
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, SoundError> {
+        Self::read(bytes).map_err(SoundError::Invalid)
+    }
+
+    fn read(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { bytes, pos: 0 };
+
+        let (id, header) = reader.chunk()?;
+        if id != b"MThd" || header.len() < 6 {
+            return Err("Not a MIDI file (missing MThd header)".to_string());
+        }
+        let track_count = u16::from_be_bytes([header[2], header[3]]);
+        let division = u16::from_be_bytes([header[4], header[5]]);
+        // The top bit marks SMPTE timing, which has no quarter note
+        let ticks_per_quarter = if division & 0x8000 == 0 { division } else { 0 };
+
+        let mut notes = Vec::new();
+        let mut tracks = 0;
+        while tracks < track_count && !reader.at_end() {
+            let (id, data) = reader.chunk()?;
+            // Unknown chunk types are allowed and skipped
+            if id == b"MTrk" {
+                read_track(data, &mut notes)
+                    .map_err(|error| format!("Track {}: {}", tracks + 1, error))?;
+                tracks += 1;
+            }
+        }
+
+        notes.sort_by_key(|note| (note.start, note.key));
+        Ok(Self {
+            ticks_per_quarter,
+            notes,
+        })
+    }
+}
+
+fn read_track(data: &[u8], notes: &mut Vec<MidiNote>) -> Result<(), String> {
+    let mut reader = Reader {
+        bytes: data,
+        pos: 0,
+    };
+    let mut time = 0u64;
+    let mut running_status = None;
+    // Start time of each sounding (channel, key)
+    let mut sounding: Vec<(u8, u8, u64)> = Vec::new();
+
+    while !reader.at_end() {
+        time += reader.variable_length()? as u64;
+
+        let mut status = reader.byte()?;
+        let first_data = if status < 0x80 {
+            // Running status: this byte is already the first data byte
+            let data = status;
+            status = running_status.ok_or("Data byte without a status byte")?;
+            Some(data)
+        } else {
+            None
+        };
+
+        match status {
+            0xFF => {
+                let kind = reader.byte()?;
+                let length = reader.variable_length()? as usize;
+                reader.skip(length)?;
+                if kind == 0x2F {
+                    break; // End of track
+                }
+            }
+            0xF0 | 0xF7 => {
+                let length = reader.variable_length()? as usize;
+                reader.skip(length)?;
+            }
+            0x80..=0xEF => {
+                running_status = Some(status);
+                let kind = status & 0xF0;
+                let channel = status & 0x0F;
+                let data1 = match first_data {
+                    Some(data) => data,
+                    None => reader.byte()?,
+                };
+                // Program change and channel pressure carry one data byte
+                let data2 = match kind {
+                    0xC0 | 0xD0 => 0,
+                    _ => reader.byte()?,
+                };
+
+                let note_on = kind == 0x90 && data2 > 0;
+                let note_off = kind == 0x80 || (kind == 0x90 && data2 == 0);
+                if note_off || note_on {
+                    // A repeated note-on also ends the note already sounding
+                    if let Some(index) = sounding
+                        .iter()
+                        .position(|&(c, k, _)| c == channel && k == data1)
+                    {
+                        let (_, _, start) = sounding.remove(index);
+                        notes.push(MidiNote {
+                            key: data1,
+                            channel,
+                            start,
+                            length: time - start,
+                        });
+                    }
+                }
+                if note_on {
+                    sounding.push((channel, data1, time));
+                }
+            }
+            _ => return Err(format!("Unexpected status byte {:#04x}", status)),
+        }
+    }
+
+    // Notes never released last until the end of the track
+    for (channel, key, start) in sounding {
+        notes.push(MidiNote {
+            key,
+            channel,
+            start,
+            length: time - start,
+        });
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn at_end(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self.bytes.get(self.pos).ok_or("Unexpected end of data")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(length)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or("Unexpected end of data")?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn skip(&mut self, length: usize) -> Result<(), String> {
+        self.take(length).map(|_| ())
+    }
+
+    /// A chunk's four-byte type and its data
+    fn chunk(&mut self) -> Result<(&'a [u8], &'a [u8]), String> {
+        let id = self.take(4)?;
+        let length = self.take(4)?;
+        let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]);
+        Ok((id, self.take(length as usize)?))
+    }
+
+    /// A variable-length quantity: seven bits per byte, high bit set on all
+    /// but the last
+    fn variable_length(&mut self) -> Result<u32, String> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.byte()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Variable-length value longer than four bytes".to_string())
+    }
+}
@@ -1,9 +1,27 @@
+pub mod abc;
+pub mod analysis;
 pub mod duration;
 pub mod interval;
 pub mod key;
 pub mod melody;
+pub mod meter;
+pub mod midi;
 pub mod note;
+pub mod phrase;
+pub mod scale;
+pub mod score;
+pub mod spelling;
+pub mod transform;
 pub mod util;
 
-pub use melody::{Melody, MelodyConfig, NoteElement};
-pub use util::{get_scale_by_name, parse_note_from_string, parse_note_notation};
+pub use abc::{format_abc, parse_abc, AbcTune};
+pub use melody::{Melody, MelodyBuilder, MelodyConfig, NoteElement};
+pub use meter::Meter;
+pub use scale::{Scale, ScaleRegistry};
+pub use score::{format_score, parse_score, parse_score_for_scale, parse_score_in_meter, Score};
+pub use spelling::{KeySpelling, PitchName};
+pub use util::{
+    format_aligned_notation, format_note_notation, get_scale_by_name, notation_ticks, parse_key,
+    parse_note_from_string, parse_note_notation, parse_note_notation_for_scale,
+    parse_note_notation_in_meter,
+};
@@ -1,9 +1,13 @@
 //! Musical note definitions and utilities
 
+use super::duration::Ticks;
+use super::spelling::PitchName;
+use crate::error::SoundError;
 use crate::Key;
-use std::time::Duration;
+use std::fmt;
 
 /// Standard musical note frequencies (in Hz) for octave 4
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Copy)]
 pub enum Note {
     C = 261,  // C4
@@ -53,6 +57,11 @@ impl Note {
         }
     }
 
+    /// MIDI note number for this note in the given octave (C4 = 60)
+    pub fn midi_number(&self, octave: u8) -> i32 {
+        (octave as i32 + 1) * 12 + self.to_semitone()
+    }
+
     /// Create note from semitone number (0-11)
     pub fn from_semitone(semitone: i32) -> Self {
         match semitone % 12 {
@@ -73,27 +82,38 @@ impl Note {
     }
 }
 
-/// Represents a musical note with duration
+impl fmt::Display for Note {
+    /// Sharp spelling; use `KeySpelling` to spell for a key
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Note::Rest => f.write_str("Rest"),
+            note => PitchName::plain(note.to_semitone(), false).fmt(f),
+        }
+    }
+}
+
+/// Represents a musical note with its length in ticks
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone)]
 pub struct MusicNote {
     pub note: Note,
     pub octave: u8,
-    pub duration: Duration,
+    pub ticks: Ticks,
 }
 
 impl MusicNote {
-    pub fn new(note: Note, octave: u8, duration: Duration) -> Self {
+    pub fn new(note: Note, octave: u8, ticks: Ticks) -> Self {
         Self {
             note,
             octave,
-            duration,
+            ticks,
         }
     }
 
     /// Create a note from a key and interval
-    pub fn from_key_interval(key: &Key, interval: i32, duration: Duration) -> Self {
-        let (note, octave) = key.note_at_interval(interval);
-        Self::new(note, octave, duration)
+    pub fn from_key_interval(key: &Key, interval: i32, ticks: Ticks) -> Result<Self, SoundError> {
+        let (note, octave) = key.note_at_interval(interval)?;
+        Ok(Self::new(note, octave, ticks))
     }
 
     pub fn frequency(&self) -> f32 {
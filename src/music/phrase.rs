@@ -0,0 +1,333 @@
+//! Repeats, endings and phrase variables in notation
+//!
+//! - `|: 1234 :|` plays the part between the signs twice, and `:|x3` three
+//!   times; a `:|` without a `|:` repeats from the start or the last repeat
+//! - `[1` and `[2` (or `[1,2`) start endings played only on those passes. An
+//!   ending before the `:|` runs up to it; one after it is for the last pass
+//!   and runs to `]` or the end of the line. Only the last ending may come
+//!   after the `:|`, so three endings are written `|: 1 [1 2 [2 3 :| [3 4`;
+//!   a second `:|` with nothing before it is an error
+//! - `$riff = 1235-` names a phrase, and `$riff` plays it in the current
+//!   register, so `^$riff` plays it an octave up. Names are letters and `_`,
+//!   so `$riff1` plays the phrase and then note 1
+//!
+//! Everything is expanded into plain note elements before a melody is built.
+//! When the notation has bar lines, repeat signs are bar lines too.
+
+use super::melody::NoteElement;
+use crate::error::SoundError;
+use std::collections::HashMap;
+
+/// Phrases defined with `$name = notation`, by name
+pub type Phrases = HashMap<String, Vec<NoteElement>>;
+
+/// Where a symbol was written, as a 1-based line and column
+pub(crate) type Position = (usize, usize);
+
+/// A symbol of notation before repeats are expanded
+#[derive(Debug, Clone)]
+pub(crate) enum Token {
+    Element(NoteElement),
+    /// `|:`
+    RepeatStart,
+    /// `:|`, with the number of passes if given as `:|x3`
+    RepeatEnd(Option<usize>),
+    /// `[1` or `[1,2`
+    EndingStart(Vec<usize>),
+    /// `]`
+    EndingEnd,
+}
+
+/// Notation with its repeats worked out
+#[derive(Debug)]
+enum Item {
+    Element(NoteElement, Position),
+    Repeat(Repeat),
+}
+
+#[derive(Debug)]
+struct Repeat {
+    /// Where the `|:` is, if there is one
+    start: Option<Position>,
+    body: Vec<Item>,
+    endings: Vec<Ending>,
+    times: usize,
+    /// Where the `:|` is
+    end: Position,
+}
+
+#[derive(Debug)]
+struct Ending {
+    passes: Vec<usize>,
+    items: Vec<Item>,
+    /// Written after the `:|`, so played on the last pass and then on
+    after: bool,
+}
+
+/// Expand repeats and endings into the elements in the order they play,
+/// each with where it was written. Mistakes are added to `errors`.
+pub(crate) fn expand(
+    tokens: &[(Token, Position)],
+    errors: &mut Vec<SoundError>,
+) -> Vec<(NoteElement, Position)> {
+    let mut parser = Parser {
+        tokens,
+        index: 0,
+        errors,
+    };
+    let items = parser.top_level();
+    let bars = tokens
+        .iter()
+        .any(|(token, _)| matches!(token, Token::Element(NoteElement::Bar)));
+    let mut elements = Vec::new();
+    flatten(&items, bars, &mut elements);
+    elements
+}
+
+struct Parser<'a> {
+    tokens: &'a [(Token, Position)],
+    index: usize,
+    errors: &'a mut Vec<SoundError>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&(Token, Position)> {
+        self.tokens.get(self.index)
+    }
+
+    fn error(&mut self, (line, column): Position, message: &str) {
+        self.errors.push(SoundError::Notation {
+            line,
+            column,
+            length: 1,
+            message: message.to_string(),
+        });
+    }
+
+    /// The whole notation; a `:|` or ending without a `|:` repeats from the
+    /// start or the end of the last repeat
+    fn top_level(&mut self) -> Vec<Item> {
+        let mut items = self.sequence();
+        while let Some((token, position)) = self.peek().cloned() {
+            match token {
+                Token::RepeatEnd(_) | Token::EndingStart(_) => {
+                    let from = items
+                        .iter()
+                        .rposition(|item| matches!(item, Item::Repeat(_)))
+                        .map_or(0, |index| index + 1);
+                    let body = items.split_off(from);
+                    if body.is_empty() {
+                        self.error(
+                            position,
+                            "Nothing to repeat here. Put every ending but the last before the :|, e.g. |: 1 [1 2 [2 3 :| [3 4",
+                        );
+                    }
+                    let repeat = self.repeat(body, None, position);
+                    items.push(Item::Repeat(repeat));
+                }
+                _ => {
+                    self.index += 1;
+                    self.error(position, "This ] does not close an ending");
+                }
+            }
+            items.extend(self.sequence());
+        }
+        items
+    }
+
+    /// Elements and complete repeats, up to a sign that ends a part
+    fn sequence(&mut self) -> Vec<Item> {
+        let mut items = Vec::new();
+        while let Some((token, position)) = self.peek().cloned() {
+            match token {
+                Token::Element(element) => {
+                    self.index += 1;
+                    items.push(Item::Element(element, position));
+                }
+                Token::RepeatStart => {
+                    self.index += 1;
+                    let repeat = self.repeat(Vec::new(), Some(position), position);
+                    items.push(Item::Repeat(repeat));
+                }
+                Token::RepeatEnd(_) | Token::EndingStart(_) | Token::EndingEnd => break,
+            }
+        }
+        items
+    }
+
+    /// The rest of a repeat whose body so far is `body`, with its `|:` at
+    /// `start` if there is one; `from` is where the repeat begins
+    fn repeat(&mut self, mut body: Vec<Item>, start: Option<Position>, from: Position) -> Repeat {
+        body.extend(self.sequence());
+        let mut endings = Vec::new();
+
+        // Endings inside the repeat, up to the `:|`
+        let (given, end) = loop {
+            let Some((token, position)) = self.peek().cloned() else {
+                self.error(from, "This repeat is never closed with :|");
+                break (Some(1), from);
+            };
+            self.index += 1;
+            match token {
+                Token::EndingStart(passes) => endings.push(Ending {
+                    passes,
+                    items: self.sequence(),
+                    after: false,
+                }),
+                Token::RepeatEnd(times) => break (times, position),
+                Token::EndingEnd if !endings.is_empty() => {
+                    if !matches!(
+                        self.peek(),
+                        Some((Token::EndingStart(_) | Token::RepeatEnd(_), _))
+                    ) {
+                        self.error(position, "Close the repeat with :| after its endings");
+                    }
+                }
+                _ => self.error(position, "This ] does not close an ending"),
+            }
+        };
+
+        // Endings after the `:|`, for the last pass
+        while let Some((Token::EndingStart(passes), _)) = self.peek().cloned() {
+            self.index += 1;
+            let items = self.sequence();
+            if let Some((Token::EndingEnd, _)) = self.peek() {
+                self.index += 1;
+            }
+            endings.push(Ending {
+                passes,
+                items,
+                after: true,
+            });
+        }
+
+        let last = endings
+            .iter()
+            .flat_map(|ending| ending.passes.iter().copied())
+            .max()
+            .unwrap_or(0);
+        let times = given.unwrap_or(last.max(2));
+        if last > times {
+            self.error(
+                end,
+                &format!(
+                    "There is an ending for pass {} but the repeat plays {} times",
+                    last, times
+                ),
+            );
+        }
+        if endings
+            .iter()
+            .any(|ending| ending.after && !ending.passes.contains(&times))
+        {
+            self.error(
+                end,
+                &format!(
+                    "An ending after :| is played on the last pass, so it must include {}",
+                    times
+                ),
+            );
+        }
+
+        Repeat {
+            start,
+            body,
+            endings,
+            times,
+            end,
+        }
+    }
+}
+
+/// Write out the items in playing order, with bar lines for the repeat
+/// signs if `bars` is set
+fn flatten(items: &[Item], bars: bool, elements: &mut Vec<(NoteElement, Position)>) {
+    for item in items {
+        match item {
+            Item::Element(element, position) => push(elements, element.clone(), *position),
+            Item::Repeat(repeat) => {
+                if let (true, Some(start)) = (bars, repeat.start) {
+                    push(elements, NoteElement::Bar, start);
+                }
+                for pass in 1..=repeat.times {
+                    flatten(&repeat.body, bars, elements);
+                    for ending in &repeat.endings {
+                        if ending.passes.contains(&pass) {
+                            flatten(&ending.items, bars, elements);
+                        }
+                    }
+                    let goes_on =
+                        pass == repeat.times && repeat.endings.iter().any(|ending| ending.after);
+                    if bars && !goes_on {
+                        push(elements, NoteElement::Bar, repeat.end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Add an element; neighbouring bar lines, such as `|` before `|:`, are one
+fn push(elements: &mut Vec<(NoteElement, Position)>, element: NoteElement, position: Position) {
+    let double = matches!(element, NoteElement::Bar)
+        && matches!(elements.last(), Some((NoteElement::Bar, _)));
+    if !double {
+        elements.push((element, position));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::music::util::{format_note_notation, parse_note_notation};
+
+    fn expanded(lines: &[&str]) -> String {
+        let lines: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        let elements = parse_note_notation(&lines).unwrap();
+        format_note_notation(&elements, 7).unwrap()
+    }
+
+    fn fails(text: &str) -> bool {
+        parse_note_notation(&[text.to_string()]).is_err()
+    }
+
+    #[test]
+    fn repeats_twice_or_as_often_as_given() {
+        assert_eq!(expanded(&["|:12:|3"]), "12123");
+        assert_eq!(expanded(&["|:12:|x3"]), "121212");
+        // Without |: the repeat starts at the beginning
+        assert_eq!(expanded(&["12:|3"]), "12123");
+    }
+
+    #[test]
+    fn endings_play_on_their_passes() {
+        assert_eq!(expanded(&["|:1[1 2:|[2 3"]), "1213");
+        assert_eq!(expanded(&["|:1[1 2]:|[2 3]4"]), "12134");
+        assert_eq!(expanded(&["|:1[1,2 2[3 3:|x3"]), "121213");
+        assert_eq!(expanded(&["|:1[1 2[2 3:|[3 4"]), "121314");
+    }
+
+    #[test]
+    fn repeats_nest_and_follow_one_another() {
+        assert_eq!(expanded(&["|:1|:2:|:|"]), "122122");
+        assert_eq!(expanded(&["|:1:|2:|"]), "1122");
+    }
+
+    #[test]
+    fn phrases_nest_and_follow_the_register() {
+        assert_eq!(expanded(&["$a = 23", "$b = $a 4", "$b^$a"]), "234^23");
+        // Digits after a name are notes
+        assert_eq!(expanded(&["$riff = 23", "$riff1$riff"]), "23123");
+    }
+
+    #[test]
+    fn rejects_bad_repeats() {
+        // A third ending after a second :| has nothing to repeat
+        assert!(fails("|:1[1 2:|[2 3:|[3 4"));
+        assert!(fails("|:12"));
+        assert!(fails("12]"));
+        assert!(fails("|:1[3 2:|x2"));
+        assert!(fails("|:1:|x3[2 3"));
+        assert!(fails("$nope"));
+        assert!(fails("$riff2 = 12"));
+    }
+}
@@ -0,0 +1,190 @@
+//! Scales by name: the built-in patterns from `interval` plus user-defined ones
+
+use super::interval;
+use crate::error::SoundError;
+
+/// A scale as semitone offsets from the root, always ending on the octave
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scale {
+    pub name: String,
+    pub intervals: Vec<i32>,
+}
+
+impl Scale {
+    /// Check and complete a scale: it must start on the root and climb
+    /// within one octave, which is added if missing
+    pub fn new(name: &str, intervals: &[i32]) -> Result<Self, SoundError> {
+        if intervals.first() != Some(&0) {
+            return Err(SoundError::InvalidScale(format!(
+                "Scale '{}' must start at 0 (the root)",
+                name
+            )));
+        }
+        if intervals.windows(2).any(|pair| pair[1] <= pair[0]) {
+            return Err(SoundError::InvalidScale(format!(
+                "Scale '{}' intervals must be ascending",
+                name
+            )));
+        }
+        if intervals
+            .iter()
+            .any(|&semitones| semitones > interval::OCTAVE)
+        {
+            return Err(SoundError::InvalidScale(format!(
+                "Scale '{}' must fit within one octave (0-12)",
+                name
+            )));
+        }
+
+        let mut intervals = intervals.to_vec();
+        if intervals.last() != Some(&interval::OCTAVE) {
+            intervals.push(interval::OCTAVE);
+        }
+        Ok(Self {
+            name: name.to_string(),
+            intervals,
+        })
+    }
+
+    /// Parse a comma-separated list such as `0,2,3,7,8`
+    pub fn parse(name: &str, text: &str) -> Result<Self, SoundError> {
+        let intervals = text
+            .split(',')
+            .map(|part| {
+                part.trim().parse::<i32>().map_err(|_| {
+                    SoundError::InvalidScale(format!(
+                        "Invalid scale interval '{}' in '{}'",
+                        part.trim(),
+                        text
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(name, &intervals)
+    }
+
+    /// Number of distinct notes, not counting the octave
+    pub fn len(&self) -> usize {
+        self.intervals.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Built-in scales as (id, aliases, display name, intervals)
+#[rustfmt::skip]
+const BUILTIN: &[(&str, &[&str], &str, &[i32])] = &[
+    // Basic scales
+    ("major", &["ionian"], "Major", &interval::MAJOR_SCALE),
+    ("minor", &["natural_minor", "aeolian"], "Natural Minor", &interval::MINOR_SCALE),
+    // Pentatonic
+    ("pentatonic", &["penta", "major_pentatonic", "pentatonic_major"], "Pentatonic Major", &interval::PENTATONIC_MAJOR),
+    ("minor_pentatonic", &["minor_penta", "pentatonic_minor", "penta_minor"], "Pentatonic Minor", &interval::PENTATONIC_MINOR),
+    ("blues_pentatonic", &["pentatonic_blues"], "Pentatonic Blues", &interval::PENTATONIC_BLUES),
+    // Church modes
+    ("dorian", &[], "Dorian", &interval::DORIAN),
+    ("phrygian", &[], "Phrygian", &interval::PHRYGIAN),
+    ("lydian", &[], "Lydian", &interval::LYDIAN),
+    ("mixolydian", &[], "Mixolydian", &interval::MIXOLYDIAN),
+    ("locrian", &[], "Locrian", &interval::LOCRIAN),
+    // Minor variations
+    ("harmonic_minor", &["harmonic"], "Harmonic Minor", &interval::HARMONIC_MINOR),
+    ("melodic_minor", &["melodic", "jazz_minor"], "Melodic Minor", &interval::MELODIC_MINOR),
+    // Blues
+    ("blues", &["blues_minor", "minor_blues"], "Blues Minor", &interval::BLUES_MINOR),
+    ("blues_major", &["major_blues"], "Blues Major", &interval::BLUES_MAJOR),
+    // Chromatic and whole tone
+    ("chromatic", &[], "Chromatic", &interval::CHROMATIC),
+    ("whole_tone", &["wholetone"], "Whole Tone", &interval::WHOLE_TONE),
+    // World music
+    ("japanese", &["hirajoshi"], "Japanese Hirajoshi", &interval::JAPANESE_HIRAJOSHI),
+    ("kumoi", &["japanese_kumoi"], "Japanese Kumoi", &interval::JAPANESE_KUMOI),
+    ("arabic", &["maqam"], "Arabic Maqam", &interval::ARABIC_MAQAM),
+    ("spanish", &["spanish_gypsy", "flamenco"], "Spanish Gypsy", &interval::SPANISH_GYPSY),
+    ("ahava_raba", &["jewish", "freygish"], "Ahava Raba", &interval::JEWISH_AHAVA_RABA),
+    // Jazz
+    ("bebop_major", &["bebop"], "Bebop Major", &interval::BEBOP_MAJOR),
+    ("bebop_minor", &[], "Bebop Minor", &interval::BEBOP_MINOR),
+    ("diminished", &["octatonic"], "Diminished", &interval::DIMINISHED),
+    ("altered", &["super_locrian"], "Altered", &interval::ALTERED),
+    // Exotic
+    ("hungarian", &["hungarian_minor"], "Hungarian Minor", &interval::HUNGARIAN_MINOR),
+    ("neapolitan_minor", &["neapolitan"], "Neapolitan Minor", &interval::NEAPOLITAN_MINOR),
+    ("persian", &[], "Persian", &interval::PERSIAN),
+    ("enigmatic", &[], "Enigmatic", &interval::ENIGMATIC),
+    // Synthetic
+    ("prometheus", &[], "Prometheus", &interval::PROMETHEUS),
+    ("tritone", &[], "Tritone", &interval::TRITONE_SCALE),
+    ("double_harmonic", &["byzantine"], "Double Harmonic", &interval::DOUBLE_HARMONIC),
+];
+
+/// A scale with the names it answers to
+#[derive(Debug, Clone)]
+pub struct ScaleEntry {
+    pub id: String,
+    pub aliases: Vec<String>,
+    pub scale: Scale,
+}
+
+/// All known scales, looked up by id or alias
+#[derive(Debug, Clone)]
+pub struct ScaleRegistry {
+    entries: Vec<ScaleEntry>,
+}
+
+impl Default for ScaleRegistry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl ScaleRegistry {
+    /// Every scale pattern in `interval`
+    pub fn builtin() -> Self {
+        let entries = BUILTIN
+            .iter()
+            .map(|(id, aliases, name, intervals)| ScaleEntry {
+                id: id.to_string(),
+                aliases: aliases.iter().map(|alias| alias.to_string()).collect(),
+                scale: Scale::new(name, intervals).expect("built-in scales are valid"),
+            })
+            .collect();
+        Self { entries }
+    }
+
+    /// Add a scale, replacing any existing one with the same id
+    pub fn add(&mut self, id: &str, aliases: &[String], scale: Scale) {
+        let id = normalize(id);
+        self.entries.retain(|entry| entry.id != id);
+        self.entries.push(ScaleEntry {
+            id,
+            aliases: aliases.iter().map(|alias| normalize(alias)).collect(),
+            scale,
+        });
+    }
+
+    /// Find a scale by id or alias, ignoring case and `-`/`_`/space differences
+    pub fn get(&self, name: &str) -> Result<&Scale, SoundError> {
+        let wanted = normalize(name);
+        // Later additions win, so custom scales can shadow built-in aliases
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.id == wanted || entry.aliases.contains(&wanted))
+            .map(|entry| &entry.scale)
+            .ok_or_else(|| SoundError::UnknownScale {
+                name: name.to_string(),
+                known: self.entries.iter().map(|entry| entry.id.clone()).collect(),
+            })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ScaleEntry> {
+        self.entries.iter()
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim().to_lowercase().replace(['-', ' '], "_")
+}
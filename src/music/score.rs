@@ -0,0 +1,408 @@
+//! Multi-voice scores read from notation files
+//!
+//! A score is a list of regions played one after another. Every notation
+//! line inside a region is a voice, and all voices of a region play together.
+//! Lines starting with `@` are directives:
+//!
+//! - `@region` starts a new region
+//! - `@id=name` names the current region
+//! - `@pan=-0.3` places the next voice in the stereo field (-1.0 left to 1.0 right)
+//! - `@gain=0.8` sets the next voice's level on the master bus
+//! - `@gate=0.5` makes the next voice's notes sound for half their length
+//! - `@wave=fm:bell` picks the next voice's wave form (same values as `--wave`)
+//! - `@inst=bass` plays the next voice with the `bass.toml` instrument preset
+//! - `@meter=3/4` checks the bar lines (`|`) of the lines that follow in 3/4
+//!
+//! Lines such as `$riff = 1235-` define phrases that any voice below can play
+//! with `$riff` (see the `phrase` module).
+//!
+//! Voice settings stick to the voice's line position, so a `@pan` given for
+//! the fourth line of one region also applies to the fourth line of the
+//! regions that follow.
+//!
+//! All voices of a region must be the same length, so they stay in step.
+
+use super::{
+    duration::{duration::sixteenth_note, Ticks},
+    melody::NoteElement,
+    meter::Meter,
+    phrase::Phrases,
+    util::{define_phrase, format_aligned_notation, notation_ticks, parse_notation},
+};
+use crate::error::SoundError;
+
+/// A parsed score
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Default)]
+pub struct Score {
+    pub regions: Vec<Region>,
+}
+
+/// A block of voices that play at the same time
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Default)]
+pub struct Region {
+    pub id: Option<String>,
+    pub voices: Vec<Voice>,
+}
+
+/// One line of notation and its settings
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub struct Voice {
+    pub note_elements: Vec<NoteElement>,
+    pub pan: f32,
+    pub gain: f32,
+    /// Share of its length each note sounds, if not the melody's
+    pub gate: Option<f32>,
+    pub wave: Option<String>,
+    pub instrument: Option<String>,
+}
+
+/// A voice followed through every region of the score
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub note_elements: Vec<NoteElement>,
+    pub pan: f32,
+    pub gain: f32,
+    pub gate: Option<f32>,
+    pub wave: Option<String>,
+    pub instrument: Option<String>,
+}
+
+/// Settings collected from directives, waiting for the next voice line
+#[derive(Debug, Clone, Default)]
+struct VoiceSettings {
+    pan: Option<f32>,
+    gain: Option<f32>,
+    gate: Option<f32>,
+    wave: Option<String>,
+    instrument: Option<String>,
+}
+
+impl Score {
+    /// Number of voices in the busiest region
+    pub fn voice_count(&self) -> usize {
+        self.regions
+            .iter()
+            .map(|region| region.voices.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Flatten the regions into one track per voice position.
+    ///
+    /// Voices that are shorter than the longest voice of their region, or
+    /// missing from a region, are padded with rests so regions stay in step.
+    /// A voice position whose settings change from one region to another
+    /// gets a track for each set of settings, resting in the regions that
+    /// use another, so every region plays with its own `@pan`, `@wave` and
+    /// the like.
+    pub fn tracks(&self) -> Vec<Track> {
+        // Tracks of each voice position
+        let mut positions: Vec<Vec<Track>> = vec![Vec::new(); self.voice_count()];
+
+        // Bar lines and marks take no time, so they are not counted
+        let symbols = |elements: &[NoteElement]| {
+            elements
+                .iter()
+                .filter(|element| element.has_length())
+                .count()
+        };
+        let pad = |track: &mut Track, length: usize| {
+            let missing = length.saturating_sub(symbols(&track.note_elements));
+            track
+                .note_elements
+                .extend(std::iter::repeat_n(NoteElement::Rest, missing));
+        };
+
+        let mut elapsed = 0;
+        for region in &self.regions {
+            let length = region
+                .voices
+                .iter()
+                .map(|voice| symbols(&voice.note_elements))
+                .max()
+                .unwrap_or(0);
+
+            for (voice, tracks) in region.voices.iter().zip(positions.iter_mut()) {
+                let index = match tracks.iter().position(|track| track.plays(voice)) {
+                    Some(index) => index,
+                    None => {
+                        tracks.push(Track {
+                            note_elements: Vec::new(),
+                            pan: voice.pan,
+                            gain: voice.gain,
+                            gate: voice.gate,
+                            wave: voice.wave.clone(),
+                            instrument: voice.instrument.clone(),
+                        });
+                        tracks.len() - 1
+                    }
+                };
+                let track = &mut tracks[index];
+                pad(track, elapsed);
+                track.note_elements.extend_from_slice(&voice.note_elements);
+            }
+            elapsed += length;
+        }
+
+        let mut tracks: Vec<Track> = positions.into_iter().flatten().collect();
+        for track in &mut tracks {
+            pad(track, elapsed);
+        }
+        tracks
+    }
+}
+
+impl Track {
+    /// Whether `voice` has this track's settings, so it can play on it
+    fn plays(&self, voice: &Voice) -> bool {
+        self.pan == voice.pan
+            && self.gain == voice.gain
+            && self.gate == voice.gate
+            && self.wave == voice.wave
+            && self.instrument == voice.instrument
+    }
+}
+
+/// Write a score back as notation file text, the inverse of `parse_score`.
+/// Every region starts with `@region`, its voices lined up column by column
+/// (see `format_aligned_notation`), and voice directives are written only
+/// where a setting changes from what the voice's line position had before.
+pub fn format_score(
+    score: &Score,
+    scale_len: usize,
+    bar_length: Option<usize>,
+) -> Result<String, SoundError> {
+    let mut text = String::new();
+    let mut previous: Vec<VoiceSettings> = Vec::new();
+
+    for (index, region) in score.regions.iter().enumerate() {
+        if index > 0 {
+            text.push('\n');
+        }
+        text.push_str("@region\n");
+        if let Some(id) = &region.id {
+            text.push_str(&format!("@id={}\n", id));
+        }
+
+        let voices: Vec<Vec<NoteElement>> = region
+            .voices
+            .iter()
+            .map(|voice| voice.note_elements.clone())
+            .collect();
+        let lines = format_aligned_notation(&voices, scale_len, bar_length)?;
+
+        for (position, (voice, line)) in region.voices.iter().zip(lines).enumerate() {
+            if previous.len() <= position {
+                previous.resize(position + 1, VoiceSettings::default());
+            }
+            let settings = &mut previous[position];
+            if voice.pan != settings.pan.unwrap_or(0.0) {
+                text.push_str(&format!("@pan={}\n", voice.pan));
+            }
+            if voice.gain != settings.gain.unwrap_or(1.0) {
+                text.push_str(&format!("@gain={}\n", voice.gain));
+            }
+            if voice.gate.is_some() && voice.gate != settings.gate {
+                if let Some(gate) = voice.gate {
+                    text.push_str(&format!("@gate={}\n", gate));
+                }
+            }
+            if voice.wave != settings.wave || voice.instrument != settings.instrument {
+                if let Some(wave) = &voice.wave {
+                    text.push_str(&format!("@wave={}\n", wave));
+                }
+                if let Some(instrument) = &voice.instrument {
+                    text.push_str(&format!("@inst={}\n", instrument));
+                }
+            }
+            *settings = VoiceSettings {
+                pan: Some(voice.pan),
+                gain: Some(voice.gain),
+                gate: voice.gate,
+                wave: voice.wave.clone(),
+                instrument: voice.instrument.clone(),
+            };
+
+            text.push_str(&line);
+            text.push('\n');
+        }
+    }
+
+    Ok(text)
+}
+
+/// Parse a score from the text of a notation file, reporting every mistake
+pub fn parse_score(text: &str) -> Result<Score, SoundError> {
+    parse(text, None, Meter::default(), sixteenth_note())
+}
+
+/// Parse a score whose voices are in a scale of `scale_len` notes, also
+/// rejecting positions the scale does not have
+pub fn parse_score_for_scale(text: &str, scale_len: usize) -> Result<Score, SoundError> {
+    parse(text, Some(scale_len), Meter::default(), sixteenth_note())
+}
+
+/// Parse a score in a scale of `scale_len` notes with each symbol
+/// `symbol_ticks` long, checking bar lines against `meter` until a `@meter`
+/// directive changes it
+pub fn parse_score_in_meter(
+    text: &str,
+    scale_len: usize,
+    meter: Meter,
+    symbol_ticks: Ticks,
+) -> Result<Score, SoundError> {
+    parse(text, Some(scale_len), meter, symbol_ticks)
+}
+
+fn parse(
+    text: &str,
+    scale_len: Option<usize>,
+    mut meter: Meter,
+    symbol_ticks: Ticks,
+) -> Result<Score, SoundError> {
+    let mut score = Score::default();
+    let mut errors = Vec::new();
+    let mut region = Region::default();
+    // Line number and length of each voice of the region, to keep them in step
+    let mut voice_lengths: Vec<(usize, Ticks)> = Vec::new();
+    let mut phrases = Phrases::new();
+    // Settings remembered per voice position across regions
+    let mut voice_settings: Vec<VoiceSettings> = Vec::new();
+    let mut pending = VoiceSettings::default();
+
+    for (number, raw_line) in text.lines().enumerate() {
+        let line = raw_line.trim();
+        let error = |message: String| SoundError::Score {
+            line: number + 1,
+            message,
+        };
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(directive) = line.strip_prefix('@') {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim())),
+                None => (directive.trim(), None),
+            };
+            match (name, value) {
+                ("region", None) => {
+                    errors.extend(check_lengths(&voice_lengths, meter));
+                    voice_lengths.clear();
+                    if !region.voices.is_empty() || region.id.is_some() {
+                        score.regions.push(std::mem::take(&mut region));
+                    }
+                }
+                ("id", Some(id)) => region.id = Some(id.to_string()),
+                ("pan", Some(pan)) => match pan.parse::<f32>() {
+                    Ok(pan) if (-1.0..=1.0).contains(&pan) => pending.pan = Some(pan),
+                    Ok(_) => errors.push(error("pan must be between -1.0 and 1.0".to_string())),
+                    Err(_) => errors.push(error(format!("invalid pan '{}'", pan))),
+                },
+                ("gain", Some(gain)) => match gain.parse::<f32>() {
+                    Ok(gain) if gain >= 0.0 => pending.gain = Some(gain),
+                    Ok(_) => errors.push(error("gain cannot be negative".to_string())),
+                    Err(_) => errors.push(error(format!("invalid gain '{}'", gain))),
+                },
+                ("gate", Some(gate)) => match gate.parse::<f32>() {
+                    Ok(gate) if gate > 0.0 && gate <= 1.0 => pending.gate = Some(gate),
+                    Ok(_) => errors.push(error("gate must be above 0 and at most 1".to_string())),
+                    Err(_) => errors.push(error(format!("invalid gate '{}'", gate))),
+                },
+                ("wave", Some(wave)) => pending.wave = Some(wave.to_string()),
+                ("inst", Some(instrument)) => pending.instrument = Some(instrument.to_string()),
+                ("meter", Some(text)) => match Meter::parse(text) {
+                    Ok(new_meter) => meter = new_meter,
+                    Err(parse_error) => errors.push(error(parse_error.to_string())),
+                },
+                _ => errors.push(error(format!("unknown directive '@{}'", directive))),
+            }
+            continue;
+        }
+
+        if define_phrase(raw_line, number + 1, scale_len, &mut phrases, &mut errors) {
+            continue;
+        }
+
+        let notation = [line.to_string()];
+        let parsed = parse_notation(&notation, scale_len, meter, symbol_ticks, &mut phrases);
+        let note_elements = match parsed {
+            Ok(note_elements) => note_elements,
+            Err(parse_error) => {
+                let indent = raw_line.chars().count() - raw_line.trim_start().chars().count();
+                errors.push(parse_error.at_line(number + 1, indent));
+                continue;
+            }
+        };
+
+        let index = region.voices.len();
+        if voice_settings.len() <= index {
+            voice_settings.resize(index + 1, VoiceSettings::default());
+        }
+        let settings = &mut voice_settings[index];
+        settings.pan = pending.pan.or(settings.pan);
+        settings.gain = pending.gain.or(settings.gain);
+        settings.gate = pending.gate.or(settings.gate);
+        // A new wave or instrument replaces whichever the voice had before
+        if pending.wave.is_some() || pending.instrument.is_some() {
+            settings.wave = pending.wave.take();
+            settings.instrument = pending.instrument.take();
+        }
+        pending = VoiceSettings::default();
+
+        voice_lengths.push((number + 1, notation_ticks(&note_elements, symbol_ticks)));
+        region.voices.push(Voice {
+            note_elements,
+            pan: settings.pan.unwrap_or(0.0),
+            gain: settings.gain.unwrap_or(1.0),
+            gate: settings.gate,
+            wave: settings.wave.clone(),
+            instrument: settings.instrument.clone(),
+        });
+    }
+
+    errors.extend(check_lengths(&voice_lengths, meter));
+
+    if !errors.is_empty() {
+        // Flatten lines with several mistakes into one list
+        let errors = errors
+            .into_iter()
+            .flat_map(|error| match error {
+                SoundError::Multiple(errors) => errors,
+                error => vec![error],
+            })
+            .collect();
+        return Err(SoundError::from_errors(errors));
+    }
+    if !region.voices.is_empty() {
+        score.regions.push(region);
+    }
+
+    Ok(score)
+}
+
+/// An error for every voice of a region whose length differs from the first
+/// voice's, given as (line number, length)
+fn check_lengths(voice_lengths: &[(usize, Ticks)], meter: Meter) -> Vec<SoundError> {
+    let Some(&(_, first)) = voice_lengths.first() else {
+        return Vec::new();
+    };
+    voice_lengths
+        .iter()
+        .enumerate()
+        .filter(|(_, &(_, length))| length != first)
+        .map(|(index, &(line, length))| SoundError::Score {
+            line,
+            message: format!(
+                "Voice {} is {} long, but the first voice of this region is {}",
+                index + 1,
+                meter.format_beats(length),
+                meter.format_beats(first)
+            ),
+        })
+        .collect()
+}
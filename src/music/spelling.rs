@@ -0,0 +1,270 @@
+//! Pitch spelling: note names with sharps, flats and double accidentals
+//!
+//! `Note` knows only the twelve pitch classes, so `A#` and `Bb` are the same
+//! note. `PitchName` keeps the letter as well, and `KeySpelling` picks the
+//! right one for a key and scale: F major is spelled with a Bb, E major with
+//! a D#.
+
+use super::interval::MAJOR_SCALE;
+use super::key::checked_octave;
+use super::note::Note;
+use crate::error::SoundError;
+use std::fmt;
+
+/// The seven natural note letters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Letter {
+    C,
+    D,
+    E,
+    F,
+    G,
+    A,
+    B,
+}
+
+const LETTERS: [Letter; 7] = [
+    Letter::C,
+    Letter::D,
+    Letter::E,
+    Letter::F,
+    Letter::G,
+    Letter::A,
+    Letter::B,
+];
+
+impl Letter {
+    /// Semitones above C of the natural note
+    pub fn semitone(self) -> i32 {
+        MAJOR_SCALE[self.index()]
+    }
+
+    fn index(self) -> usize {
+        LETTERS.iter().position(|&letter| letter == self).unwrap()
+    }
+
+    /// The letter `steps` letters higher (or lower), wrapping around
+    pub fn offset(self, steps: i32) -> Letter {
+        LETTERS[(self.index() as i32 + steps).rem_euclid(7) as usize]
+    }
+
+    pub(crate) fn from_char(c: char) -> Option<Letter> {
+        match c.to_ascii_uppercase() {
+            'C' => Some(Letter::C),
+            'D' => Some(Letter::D),
+            'E' => Some(Letter::E),
+            'F' => Some(Letter::F),
+            'G' => Some(Letter::G),
+            'A' => Some(Letter::A),
+            'B' => Some(Letter::B),
+            _ => None,
+        }
+    }
+}
+
+/// A spelled pitch class such as `Bb`, `C#` or `Ebb`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PitchName {
+    pub letter: Letter,
+    /// Semitones of sharps (positive) or flats (negative), -2 to 2
+    pub accidental: i8,
+}
+
+impl PitchName {
+    pub fn new(letter: Letter, accidental: i8) -> Self {
+        Self { letter, accidental }
+    }
+
+    /// Parse a note name: a letter followed by `#`, `s` or `♯` for sharps,
+    /// `b` or `♭` for flats, or `x` for a double sharp (`C#`, `Cs`, `Bb`,
+    /// `Ebb`, `F##`, `Fx`)
+    pub fn parse(name: &str) -> Result<Self, SoundError> {
+        let mut chars = name.trim().chars();
+        let letter = chars
+            .next()
+            .and_then(Letter::from_char)
+            .ok_or_else(|| SoundError::InvalidNote(format!("Unknown note: {}", name)))?;
+
+        let mut accidental = 0i8;
+        for c in chars {
+            accidental += match c {
+                '#' | 's' | 'S' | '♯' => 1,
+                'b' | 'B' | '♭' => -1,
+                'x' | '𝄪' => 2,
+                '♮' => 0,
+                _ => return Err(SoundError::InvalidNote(format!("Unknown note: {}", name))),
+            };
+            if accidental.abs() > 2 {
+                return Err(SoundError::InvalidNote(format!(
+                    "Too many accidentals in {} (double sharps and flats at most)",
+                    name
+                )));
+            }
+        }
+        Ok(Self { letter, accidental })
+    }
+
+    /// Semitones above C, 0-11
+    pub fn semitone(&self) -> i32 {
+        (self.letter.semitone() + self.accidental as i32).rem_euclid(12)
+    }
+
+    pub fn note(&self) -> Note {
+        Note::from_semitone(self.semitone())
+    }
+
+    /// Octave change caused by spelling across C: `B#3` sounds as C4 and
+    /// `Cb4` as B3
+    pub fn octave_carry(&self) -> i32 {
+        (self.letter.semitone() + self.accidental as i32).div_euclid(12)
+    }
+
+    /// The usual name of a pitch class, with sharps or flats
+    pub fn plain(semitone: i32, flats: bool) -> Self {
+        let semitone = semitone.rem_euclid(12);
+        if let Some(letter) = LETTERS.iter().find(|l| l.semitone() == semitone) {
+            return Self::new(*letter, 0);
+        }
+        if flats {
+            Self::new(Note::from_semitone(semitone + 1).natural_letter(), -1)
+        } else {
+            Self::new(Note::from_semitone(semitone - 1 + 12).natural_letter(), 1)
+        }
+    }
+}
+
+impl fmt::Display for PitchName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.letter)?;
+        let symbol = if self.accidental > 0 { "#" } else { "b" };
+        for _ in 0..self.accidental.abs() {
+            f.write_str(symbol)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse scientific pitch notation: a note name and an octave, e.g. `A4`,
+/// `Eb3` or `C#5`. The octave is that of the sounding note, so `B#3` is C4.
+pub fn parse_pitch(text: &str) -> Result<(PitchName, u8), SoundError> {
+    let split = text
+        .find(|c: char| c.is_ascii_digit() || c == '-')
+        .ok_or_else(|| SoundError::InvalidNote(format!("Missing octave in note: {}", text)))?;
+    let (name, octave) = text.split_at(split);
+    let name = PitchName::parse(name)?;
+    let octave: i32 = octave
+        .parse()
+        .map_err(|_| SoundError::InvalidNote(format!("Invalid octave in note: {}", text)))?;
+    let sounding = checked_octave(octave + name.octave_carry())
+        .map_err(|error| SoundError::OutOfRange(format!("{} in note: {}", error, text)))?;
+    Ok((name, sounding))
+}
+
+/// Names notes the way a key and scale would write them
+#[derive(Debug, Clone)]
+pub struct KeySpelling {
+    root: PitchName,
+    /// Scale intervals, without the octave
+    intervals: Vec<i32>,
+    /// Seven-note scale whose letters spell the notes it shares
+    parent: Vec<i32>,
+    /// Spell other notes with flats rather than sharps
+    flats: bool,
+}
+
+impl KeySpelling {
+    /// Spelling for a scale on a root spelled as given
+    pub fn new(root: PitchName, scale_intervals: &[i32]) -> Self {
+        let intervals: Vec<i32> = scale_intervals
+            .iter()
+            .copied()
+            .filter(|&interval| interval < 12)
+            .collect();
+
+        // A seven-note scale shows its own accidentals; other scales borrow
+        // them from the major or minor scale on the same root
+        let parent: Vec<i32> = if intervals.len() == 7 {
+            intervals.clone()
+        } else if intervals.contains(&3) && !intervals.contains(&4) {
+            super::interval::MINOR_SCALE[..7].to_vec()
+        } else {
+            MAJOR_SCALE[..7].to_vec()
+        };
+        let accidentals: i32 = (0..7)
+            .map(|degree| spell_degree(root, &parent, degree).accidental as i32)
+            .sum();
+        let flats = root.accidental < 0 || (root.accidental == 0 && accidentals < 0);
+
+        Self {
+            root,
+            intervals,
+            parent,
+            flats,
+        }
+    }
+
+    /// Spelling for a root given only as a pitch class, choosing whichever
+    /// name needs fewer accidentals (Eb major rather than D# major)
+    pub fn for_note(root: Note, scale_intervals: &[i32]) -> Self {
+        let sharp = Self::new(PitchName::plain(root.to_semitone(), false), scale_intervals);
+        let flat = Self::new(PitchName::plain(root.to_semitone(), true), scale_intervals);
+        if flat.accidental_count() < sharp.accidental_count() {
+            flat
+        } else {
+            sharp
+        }
+    }
+
+    pub fn root(&self) -> PitchName {
+        self.root
+    }
+
+    /// Name of the note `interval` semitones above the root
+    pub fn name(&self, interval: i32) -> PitchName {
+        let within = interval.rem_euclid(12);
+        if let Some(degree) = self.parent.iter().position(|&i| i == within) {
+            let name = spell_degree(self.root, &self.parent, degree);
+            if name.accidental.abs() <= 2 {
+                return name;
+            }
+        }
+        if within == 0 {
+            return self.root;
+        }
+        PitchName::plain(self.root.semitone() + within, self.flats)
+    }
+
+    /// Name of a note, which need not be in the scale
+    pub fn name_note(&self, note: Note) -> PitchName {
+        self.name(note.to_semitone() - self.root.semitone())
+    }
+
+    fn accidental_count(&self) -> i32 {
+        (0..12)
+            .filter(|i| self.intervals.contains(i))
+            .map(|i| self.name(i).accidental.abs() as i32)
+            .sum()
+    }
+}
+
+/// A seven-note scale uses each letter once, so degree `n` takes the letter
+/// `n` above the root's and whatever accidental reaches the right pitch
+fn spell_degree(root: PitchName, intervals: &[i32], degree: usize) -> PitchName {
+    let letter = root.letter.offset(degree as i32);
+    let target = root.semitone() + intervals[degree];
+    // Smallest signed distance from the natural letter to the target
+    let accidental = (target - letter.semitone() + 6).rem_euclid(12) - 6;
+    PitchName::new(letter, accidental as i8)
+}
+
+impl Note {
+    /// Letter of a natural note, or of the note below for sharps
+    fn natural_letter(&self) -> Letter {
+        let semitone = self.to_semitone().max(0);
+        *LETTERS
+            .iter()
+            .rev()
+            .find(|letter| letter.semitone() <= semitone)
+            .unwrap()
+    }
+}
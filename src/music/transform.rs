@@ -0,0 +1,171 @@
+//! Composition tools that rework scale-degree melodies
+//!
+//! Notation is first grouped into events, each a note (as a scale step
+//! counted from the root, so the octave is part of the number) or a rest,
+//! with its length in notation symbols. Transformations work on events and
+//! the result is turned back into note elements.
+
+use super::melody::NoteElement;
+use crate::error::SoundError;
+
+/// A note or rest and how many notation symbols it lasts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Event {
+    /// Scale steps above the root (7 is the octave in a seven-note scale),
+    /// or `None` for a rest
+    pub step: Option<i32>,
+    pub length: usize,
+}
+
+/// Group note elements into events for a scale of `scale_len` notes
+pub fn to_events(elements: &[NoteElement], scale_len: usize) -> Result<Vec<Event>, SoundError> {
+    let mut events: Vec<Event> = Vec::new();
+    let mut tied = false;
+    for element in elements {
+        match element {
+            NoteElement::Note(position, octave) => {
+                if *position == 0 || *position > scale_len + 1 {
+                    return Err(SoundError::OutOfRange(format!(
+                        "Note position {} is out of range for this scale",
+                        position
+                    )));
+                }
+                let step = Some(*position as i32 - 1 + octave * scale_len as i32);
+                match events.last_mut() {
+                    // A tie to the same pitch makes one longer note
+                    Some(event) if tied && event.step == step => event.length += 1,
+                    _ => events.push(Event { step, length: 1 }),
+                }
+            }
+            NoteElement::Sustain => match events.last_mut() {
+                Some(event) if event.step.is_some() => event.length += 1,
+                // Sustains without a preceding note are treated as rests
+                _ => events.push(Event {
+                    step: None,
+                    length: 1,
+                }),
+            },
+            NoteElement::Rest => events.push(Event {
+                step: None,
+                length: 1,
+            }),
+            // Bar lines and articulation are not kept; they take no time
+            NoteElement::Bar | NoteElement::Staccato | NoteElement::Legato => {}
+            NoteElement::Tie => {}
+        }
+        // A tie reaches across bar lines to the next note
+        tied = match element {
+            NoteElement::Tie => true,
+            NoteElement::Bar | NoteElement::Staccato | NoteElement::Legato => tied,
+            _ => false,
+        };
+    }
+    Ok(events)
+}
+
+/// Turn events back into note elements, with positions 1 to `scale_len`
+pub fn to_elements(events: &[Event], scale_len: usize) -> Vec<NoteElement> {
+    let scale_len = scale_len as i32;
+    let mut elements = Vec::new();
+    for event in events {
+        match event.step {
+            Some(step) => {
+                let position = step.rem_euclid(scale_len) as usize + 1;
+                elements.push(NoteElement::Note(position, step.div_euclid(scale_len)));
+                elements.extend((1..event.length).map(|_| NoteElement::Sustain));
+            }
+            None => elements.extend((0..event.length).map(|_| NoteElement::Rest)),
+        }
+    }
+    elements
+}
+
+/// Move every note up (or down, for negative `steps`) along the scale
+pub fn shift(events: &[Event], steps: i32) -> Vec<Event> {
+    events
+        .iter()
+        .map(|event| Event {
+            step: event.step.map(|step| step + steps),
+            ..*event
+        })
+        .collect()
+}
+
+/// Mirror the melody around its first note, within the scale
+pub fn invert(events: &[Event]) -> Vec<Event> {
+    let Some(axis) = events.iter().find_map(|event| event.step) else {
+        return events.to_vec();
+    };
+    events
+        .iter()
+        .map(|event| Event {
+            step: event.step.map(|step| 2 * axis - step),
+            ..*event
+        })
+        .collect()
+}
+
+/// Play the melody backwards, each note keeping its own length
+pub fn retrograde(events: &[Event]) -> Vec<Event> {
+    events.iter().rev().copied().collect()
+}
+
+/// Multiply every length by `numerator / denominator`, keeping each event
+/// at least one symbol long
+pub fn stretch(events: &[Event], numerator: usize, denominator: usize) -> Vec<Event> {
+    events
+        .iter()
+        .map(|event| Event {
+            length: (event.length * numerator / denominator.max(1)).max(1),
+            ..*event
+        })
+        .collect()
+}
+
+/// Keep the rhythm but move the sequence of pitches `by` notes along it,
+/// wrapping around
+pub fn rotate(events: &[Event], by: i32) -> Vec<Event> {
+    let steps: Vec<i32> = events.iter().filter_map(|event| event.step).collect();
+    if steps.is_empty() {
+        return events.to_vec();
+    }
+
+    let offset = by.rem_euclid(steps.len() as i32) as usize;
+    let mut rotated = steps.iter().cycle().skip(offset);
+    events
+        .iter()
+        .map(|event| Event {
+            step: event.step.and(rotated.next().copied()),
+            ..*event
+        })
+        .collect()
+}
+
+/// Carry a melody from one scale into another. Scales with the same number
+/// of notes keep every degree (a major tune played in dorian); otherwise each
+/// note moves to the nearest pitch of the new scale.
+pub fn change_mode(events: &[Event], from: &[i32], to: &[i32]) -> Vec<Event> {
+    let from_len = from.len() as i32 - 1;
+    let to_len = to.len() as i32 - 1;
+    if from_len == to_len {
+        return events.to_vec();
+    }
+
+    let semitones = |scale: &[i32], len: i32, step: i32| {
+        step.div_euclid(len) * 12 + scale[step.rem_euclid(len) as usize]
+    };
+    events
+        .iter()
+        .map(|event| Event {
+            step: event.step.map(|step| {
+                let target = semitones(from, from_len, step);
+                // The answer is within an octave of the same octave's root
+                let first = target.div_euclid(12) * to_len - to_len;
+                (first..=first + 3 * to_len)
+                    .min_by_key(|&candidate| (semitones(to, to_len, candidate) - target).abs())
+                    .unwrap_or(step)
+            }),
+            ..*event
+        })
+        .collect()
+}
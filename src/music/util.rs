@@ -1,60 +1,35 @@
-use super::{interval, melody::NoteElement, note::Note};
-
-pub fn get_scale_by_name(name: &str) -> Result<(&'static [i32], String), String> {
-    match name.to_lowercase().as_str() {
-        // Basic scales
-        "major" => Ok((&interval::MAJOR_SCALE, "Major".to_string())),
-        "minor" => Ok((&interval::MINOR_SCALE, "Natural Minor".to_string())),
-
-        // Pentatonic
-        "pentatonic" | "penta" => Ok((&interval::PENTATONIC_MAJOR, "Pentatonic Major".to_string())),
-        "minor_pentatonic" | "minor_penta" => {
-            Ok((&interval::PENTATONIC_MINOR, "Pentatonic Minor".to_string()))
-        }
-        "blues" => Ok((&interval::BLUES_MINOR, "Blues Minor".to_string())),
-
-        // Church modes
-        "dorian" => Ok((&interval::DORIAN, "Dorian".to_string())),
-        "phrygian" => Ok((&interval::PHRYGIAN, "Phrygian".to_string())),
-        "lydian" => Ok((&interval::LYDIAN, "Lydian".to_string())),
-        "mixolydian" => Ok((&interval::MIXOLYDIAN, "Mixolydian".to_string())),
-        "locrian" => Ok((&interval::LOCRIAN, "Locrian".to_string())),
-
-        // Exotic scales
-        "harmonic_minor" | "harmonic" => {
-            Ok((&interval::HARMONIC_MINOR, "Harmonic Minor".to_string()))
-        }
-        "hungarian" => Ok((&interval::HUNGARIAN_MINOR, "Hungarian Minor".to_string())),
-        "japanese" => Ok((
-            &interval::JAPANESE_HIRAJOSHI,
-            "Japanese Hirajoshi".to_string(),
-        )),
-        "arabic" => Ok((&interval::ARABIC_MAQAM, "Arabic Maqam".to_string())),
-        "spanish" => Ok((&interval::SPANISH_GYPSY, "Spanish Gypsy".to_string())),
-        "whole_tone" | "wholetone" => Ok((&interval::WHOLE_TONE, "Whole Tone".to_string())),
-
-        _ => Err(format!(
-            "Unknown scale: {}. Try: major, minor, dorian, blues, japanese, etc.",
-            name
-        )),
-    }
-}
-
-pub fn parse_note_from_string(note_str: &str) -> Result<Note, String> {
-    match note_str.to_uppercase().as_str() {
-        "C" => Ok(Note::C),
-        "CS" | "C#" => Ok(Note::Cs),
-        "D" => Ok(Note::D),
-        "DS" | "D#" => Ok(Note::Ds),
-        "E" => Ok(Note::E),
-        "F" => Ok(Note::F),
-        "FS" | "F#" => Ok(Note::Fs),
-        "G" => Ok(Note::G),
-        "GS" | "G#" => Ok(Note::Gs),
-        "A" => Ok(Note::A),
-        "AS" | "A#" => Ok(Note::As),
-        "B" => Ok(Note::B),
-        _ => Err(format!("Unknown note: {}", note_str)),
+use super::{
+    duration::{duration::sixteenth_note, Ticks},
+    melody::NoteElement,
+    meter::Meter,
+    note::Note,
+    phrase::{expand, Phrases, Position, Token},
+    scale::{Scale, ScaleRegistry},
+    spelling::{parse_pitch, PitchName},
+};
+use crate::error::SoundError;
+
+/// Look up a built-in scale by name or alias
+pub fn get_scale_by_name(name: &str) -> Result<Scale, SoundError> {
+    ScaleRegistry::builtin().get(name).cloned()
+}
+
+/// Parse a note name such as `C`, `F#`, `Bb` or `Ebb` into its pitch class
+pub fn parse_note_from_string(note_str: &str) -> Result<Note, SoundError> {
+    PitchName::parse(note_str).map(|name| name.note())
+}
+
+/// Parse a note name followed by its octave, e.g. `A3`, `C#5` or `Eb3`
+pub fn parse_note_with_octave(note_str: &str) -> Result<(Note, u8), SoundError> {
+    parse_pitch(note_str).map(|(name, octave)| (name.note(), octave))
+}
+
+/// Parse a key's root with an optional octave: `Eb` (octave 4) or `Eb3`
+pub fn parse_key(key_str: &str) -> Result<(PitchName, u8), SoundError> {
+    if key_str.contains(|c: char| c.is_ascii_digit()) {
+        parse_pitch(key_str)
+    } else {
+        Ok((PitchName::parse(key_str)?, 4))
     }
 }
 
@@ -62,52 +37,693 @@ pub fn parse_note_from_string(note_str: &str) -> Result<Note, String> {
 /// Examples: "1..3-5" -> [Note(1,0), Rest, Rest, Note(3,0), Sustain, Note(5,0)]
 /// "123" -> [Note(1,0), Note(2,0), Note(3,0)] (consecutive digits treated as separate notes)
 /// "1^234v5" -> [Note(1,0), Note(2,1), Note(3,1), Note(4,1), Note(5,0)] (modal octave shifting)
-pub fn parse_note_notation(note_strings: &[String]) -> Result<Vec<NoteElement>, String> {
-    let mut elements = Vec::new();
+/// "1234|5---|" -> bar lines (`|`), checked against 4/4 in sixteenth notes
+/// "|:12:|3" -> [Bar, Note(1,0), Note(2,0), Bar, Note(1,0), Note(2,0), Bar, Note(3,0)]
+/// (repeats, endings and `$name = ...` phrases are expanded, see `phrase`)
+/// Errors give the line (which string) and column (which character), from 1.
+/// Every mistake is reported, as `SoundError::Multiple` when there are several.
+pub fn parse_note_notation(note_strings: &[String]) -> Result<Vec<NoteElement>, SoundError> {
+    parse_notation(
+        note_strings,
+        None,
+        Meter::default(),
+        sixteenth_note(),
+        &mut Phrases::new(),
+    )
+}
+
+/// Parse notation for a scale of `scale_len` notes, also rejecting positions
+/// the scale does not have (`scale_len + 1` is the octave)
+pub fn parse_note_notation_for_scale(
+    note_strings: &[String],
+    scale_len: usize,
+) -> Result<Vec<NoteElement>, SoundError> {
+    parse_notation(
+        note_strings,
+        Some(scale_len),
+        Meter::default(),
+        sixteenth_note(),
+        &mut Phrases::new(),
+    )
+}
+
+/// Parse notation for a scale of `scale_len` notes whose bar lines are
+/// checked against `meter`, with each symbol `symbol_ticks` long. The bar
+/// before the first bar line may be a pickup and the last bar may be short,
+/// but every bar in between must be exactly one bar of the meter.
+pub fn parse_note_notation_in_meter(
+    note_strings: &[String],
+    scale_len: usize,
+    meter: Meter,
+    symbol_ticks: Ticks,
+) -> Result<Vec<NoteElement>, SoundError> {
+    parse_notation(
+        note_strings,
+        Some(scale_len),
+        meter,
+        symbol_ticks,
+        &mut Phrases::new(),
+    )
+}
+
+pub(crate) fn parse_notation(
+    note_strings: &[String],
+    scale_len: Option<usize>,
+    meter: Meter,
+    symbol_ticks: Ticks,
+    phrases: &mut Phrases,
+) -> Result<Vec<NoteElement>, SoundError> {
+    let mut errors = Vec::new();
+    let mut tokens = Vec::new();
+    for (line, note_string) in note_strings.iter().enumerate() {
+        if !define_phrase(note_string, line + 1, scale_len, phrases, &mut errors) {
+            let mut reader = Reader::new(scale_len, phrases, &mut errors);
+            reader.read(note_string, line + 1, 0);
+            tokens.append(&mut reader.tokens);
+        }
+    }
+    let played = expand(&tokens, &mut errors);
+    if let Some(len) = scale_len {
+        check_ties(&played, len, &mut errors);
+    }
+
+    // Bar lines are checked in playing order, once per place they are written
+    let mut bars = BarCounter::new(meter, symbol_ticks);
+    let mut bar_errors: Vec<SoundError> = Vec::new();
+    let mut bar_error = |(line, column): Position, message: String| {
+        if !bar_errors
+            .iter()
+            .any(|error| error.location() == Some((line, column)))
+        {
+            bar_errors.push(SoundError::Notation {
+                line,
+                column,
+                length: 1,
+                message,
+            });
+        }
+    };
+    for (element, position) in &played {
+        match element {
+            NoteElement::Bar => {
+                if let Some(message) = bars.bar_line() {
+                    bar_error(*position, message);
+                }
+            }
+            element => bars.add(element),
+        }
+    }
+    if let (Some(message), Some((_, position))) = (bars.finish(), played.last()) {
+        bar_error(*position, message);
+    }
+    errors.extend(bar_errors);
+
+    if !errors.is_empty() {
+        return Err(SoundError::from_errors(errors));
+    }
+    let elements: Vec<NoteElement> = played.into_iter().map(|(element, _)| element).collect();
+    if elements.is_empty() {
+        return Err(SoundError::Invalid("No notes provided".to_string()));
+    }
+
+    Ok(elements)
+}
+
+/// Check that every tie joins a note to a later note of the same pitch; a
+/// tie at the very end may carry on into the next region
+fn check_ties(played: &[(NoteElement, Position)], scale_len: usize, errors: &mut Vec<SoundError>) {
+    // Scale steps from the root, so `8` and `^1` are the same pitch
+    let step = |element: &NoteElement| match element {
+        NoteElement::Note(position, octave) => {
+            Some(*position as i32 - 1 + octave * scale_len as i32)
+        }
+        _ => None,
+    };
+    let mut last_note = None;
+    for (index, (element, (line, column))) in played.iter().enumerate() {
+        match element {
+            NoteElement::Note(..) => last_note = step(element),
+            NoteElement::Rest => last_note = None,
+            NoteElement::Tie => {
+                let next = played[index + 1..]
+                    .iter()
+                    .find(|(element, _)| !matches!(element, NoteElement::Bar))
+                    .map(|(element, _)| step(element));
+                let message = match (last_note, next) {
+                    (None, _) => "A tie (~) needs a note before it",
+                    (_, Some(None)) => "A tie (~) must be followed by a note",
+                    (Some(last), Some(Some(next))) if last != next => {
+                        "A tie (~) joins notes of the same pitch"
+                    }
+                    _ => continue,
+                };
+                errors.push(SoundError::Notation {
+                    line: *line,
+                    column: *column,
+                    length: 1,
+                    message: message.to_string(),
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Read `$name = notation` into `phrases`, written on `line`; false if the
+/// text is not a phrase definition. Mistakes are added to `errors`.
+pub(crate) fn define_phrase(
+    text: &str,
+    line: usize,
+    scale_len: Option<usize>,
+    phrases: &mut Phrases,
+    errors: &mut Vec<SoundError>,
+) -> bool {
+    let Some((name, body)) = text
+        .trim_start()
+        .strip_prefix('$')
+        .and_then(|rest| rest.split_once('='))
+    else {
+        return false;
+    };
+    let name = name.trim();
+    // Columns of the name and of the notation after the `=`
+    let name_column = text
+        .find('$')
+        .map_or(0, |index| text[..index].chars().count())
+        + 1;
+    let body_column = text.chars().count() - body.chars().count();
+
+    if !is_phrase_name(name) {
+        errors.push(SoundError::Notation {
+            line,
+            column: name_column,
+            length: 1,
+            message: format!("Invalid phrase name '${}'. Use letters and _", name),
+        });
+        return true;
+    }
+
+    let count = errors.len();
+    let mut reader = Reader::new(scale_len, phrases, errors);
+    reader.read(body, line, body_column);
+    let tokens = std::mem::take(&mut reader.tokens);
+    let elements: Vec<NoteElement> = expand(&tokens, errors)
+        .into_iter()
+        .map(|(element, _)| element)
+        .collect();
+    if errors.len() == count && elements.is_empty() {
+        errors.push(SoundError::Notation {
+            line,
+            column: name_column,
+            length: 1,
+            message: format!("Phrase ${} has no notes", name),
+        });
+    }
+    phrases.insert(name.to_string(), elements);
+    true
+}
+
+/// Names have no digits, so `$riff1` plays `$riff` and then note 1
+fn is_phrase_char(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_phrase_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(is_phrase_char)
+}
+
+/// Turns lines of notation into tokens, expanding phrases where they are used
+struct Reader<'a> {
+    scale_len: Option<usize>,
+    phrases: &'a Phrases,
+    errors: &'a mut Vec<SoundError>,
+    tokens: Vec<(Token, Position)>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(
+        scale_len: Option<usize>,
+        phrases: &'a Phrases,
+        errors: &'a mut Vec<SoundError>,
+    ) -> Self {
+        Self {
+            scale_len,
+            phrases,
+            errors,
+            tokens: Vec::new(),
+        }
+    }
 
-    for note_string in note_strings {
-        let mut chars = note_string.chars().peekable();
+    /// Read one line of notation that starts `offset` characters into the
+    /// written line
+    fn read(&mut self, text: &str, line: usize, offset: usize) {
+        let chars: Vec<char> = text.chars().collect();
         let mut current_octave_offset = 0i32; // Track current octave register
+        let mut index = 0;
 
-        while let Some(ch) = chars.next() {
-            match ch {
+        while index < chars.len() {
+            let ch = chars[index];
+            let position = (line, offset + index + 1);
+            let mut error = |message: String| {
+                self.errors.push(SoundError::Notation {
+                    line: position.0,
+                    column: position.1,
+                    length: 1,
+                    message,
+                })
+            };
+            // Characters after the first that belong to this symbol
+            let mut extra = 0;
+            let token = match ch {
                 '1'..='9' => {
                     // Each digit is treated as a separate note (1-9 only, no 0)
-                    let position = ch.to_digit(10).unwrap() as usize;
-                    elements.push(NoteElement::Note(position, current_octave_offset));
+                    let note = ch.to_digit(10).unwrap() as usize;
+                    match self.scale_len {
+                        Some(len) if note > len + 1 => {
+                            error(format!(
+                                "Note position {} is out of range for this scale (use 1-{})",
+                                note,
+                                len + 1
+                            ));
+                            None
+                        }
+                        _ => Some(Token::Element(NoteElement::Note(
+                            note,
+                            current_octave_offset,
+                        ))),
+                    }
                 }
                 '0' => {
-                    return Err("Note position 0 is invalid. Use positions 1-9.".to_string());
+                    error("Note position 0 is invalid. Use positions 1-9.".to_string());
+                    None
                 }
-                '.' => {
-                    // Add a rest
-                    elements.push(NoteElement::Rest);
-                }
-                '-' => {
-                    // Add a sustain
-                    elements.push(NoteElement::Sustain);
+                // Add a rest
+                '.' => Some(Token::Element(NoteElement::Rest)),
+                // Add a sustain
+                '-' => Some(Token::Element(NoteElement::Sustain)),
+                // Tie the last note to the next one
+                '~' => Some(Token::Element(NoteElement::Tie)),
+                '\'' | '_' => {
+                    // Articulation, right after the note it belongs to
+                    if matches!(
+                        self.tokens.last(),
+                        Some((Token::Element(NoteElement::Note(..)), _))
+                    ) {
+                        Some(Token::Element(if ch == '_' {
+                            NoteElement::Legato
+                        } else {
+                            NoteElement::Staccato
+                        }))
+                    } else {
+                        let mark = if ch == '_' { "legato" } else { "staccato" };
+                        error(format!(
+                            "A {} mark ({}) must come right after a note",
+                            mark, ch
+                        ));
+                        None
+                    }
                 }
                 '^' => {
                     // Shift octave register up by one
                     current_octave_offset += 1;
+                    None
                 }
                 'v' => {
                     // Shift octave register down by one
                     current_octave_offset -= 1;
+                    None
+                }
+                // `|:|` is a bar line and the end of a repeat
+                '|' if chars.get(index + 1) == Some(&':') && chars.get(index + 2) != Some(&'|') => {
+                    extra = 1;
+                    Some(Token::RepeatStart)
+                }
+                // A bar line, which takes no time
+                '|' => Some(Token::Element(NoteElement::Bar)),
+                ':' if chars.get(index + 1) == Some(&'|') => {
+                    extra = 1;
+                    let mut times = None;
+                    if chars.get(index + 2) == Some(&'x') {
+                        let digits: String = chars[index + 3..]
+                            .iter()
+                            .take_while(|c| c.is_ascii_digit())
+                            .collect();
+                        match digits.parse::<usize>() {
+                            Ok(count) if count > 0 => times = Some(count),
+                            _ => error("Write the number of passes after x, e.g. :|x3".to_string()),
+                        }
+                        extra += 1 + digits.len();
+                    }
+                    Some(Token::RepeatEnd(times))
+                }
+                '[' => {
+                    let label: String = chars[index + 1..]
+                        .iter()
+                        .take_while(|c| c.is_ascii_digit() || **c == ',')
+                        .collect();
+                    extra = label.chars().count();
+                    let passes: Option<Vec<usize>> = label
+                        .split(',')
+                        .map(|pass| pass.parse().ok().filter(|&pass: &usize| pass > 0))
+                        .collect();
+                    match passes {
+                        Some(passes) => Some(Token::EndingStart(passes)),
+                        None => {
+                            error("Number the ending with its passes, e.g. [1 or [1,2".to_string());
+                            None
+                        }
+                    }
+                }
+                ']' => Some(Token::EndingEnd),
+                '$' => {
+                    let name: String = chars[index + 1..]
+                        .iter()
+                        .take_while(|c| is_phrase_char(**c))
+                        .collect();
+                    extra = name.chars().count();
+                    match self.phrases.get(&name) {
+                        Some(elements) => {
+                            // Play the phrase in the current register
+                            for element in elements {
+                                let element = match element {
+                                    NoteElement::Note(note, octave) => {
+                                        NoteElement::Note(*note, octave + current_octave_offset)
+                                    }
+                                    element => element.clone(),
+                                };
+                                self.tokens.push((Token::Element(element), position));
+                            }
+                        }
+                        None => error(format!(
+                            "Unknown phrase '${}'. Define it on a line of its own first, e.g. ${} = 1235-",
+                            name,
+                            if is_phrase_name(&name) { name.as_str() } else { "riff" }
+                        )),
+                    }
+                    None
                 }
                 ' ' | '\t' => {
                     // Whitespace - ignore
+                    None
                 }
                 _ => {
-                    return Err(format!("Invalid character '{}' in note notation. Use digits 1-9, dots (.), dashes (-), carets (^), and v's for octaves", ch));
+                    // Report a run of invalid characters, with the spaces between them, once
+                    let rest = chars[index + 1..]
+                        .iter()
+                        .take_while(|c| c.is_whitespace() || !is_notation_char(**c));
+                    let run = std::iter::once(&ch).chain(rest).collect::<String>();
+                    let run = run.trim_end();
+                    let length = run.chars().count();
+                    extra = length - 1;
+                    self.errors.push(SoundError::Notation {
+                        line: position.0,
+                        column: position.1,
+                        length,
+                        message: format!("Invalid {} '{}' in note notation. Use digits 1-9, dots (.), dashes (-), ties (~), ' and _ for staccato and legato, carets (^), v's for octaves, | for bar lines, |: :| [1 ] for repeats and $ for phrases",
+                            if length == 1 { "character" } else { "characters" },
+                            run
+                        ),
+                    });
+                    None
                 }
+            };
+            if let Some(token) = token {
+                self.tokens.push((token, position));
             }
+            index += 1 + extra;
         }
     }
+}
 
-    if elements.is_empty() {
-        return Err("No notes provided".to_string());
+/// Whether `c` can start a symbol of note notation
+fn is_notation_char(c: char) -> bool {
+    c.is_ascii_digit() || ".-~'_^v|:[]$ \t".contains(c)
+}
+
+/// Length of notation as a melody plays it, each symbol `symbol_ticks` long
+pub fn notation_ticks(elements: &[NoteElement], symbol_ticks: Ticks) -> Ticks {
+    let mut bars = BarCounter::new(Meter::default(), symbol_ticks);
+    for element in elements {
+        bars.add(element);
+    }
+    bars.total
+}
+
+/// Running length of notation and of its current bar, for checking bar lines
+struct BarCounter {
+    meter: Meter,
+    symbol_ticks: Ticks,
+    total: Ticks,
+    /// Length of the bar so far
+    ticks: Ticks,
+    /// Number of the current bar, from 1
+    bar: usize,
+}
+
+impl BarCounter {
+    fn new(meter: Meter, symbol_ticks: Ticks) -> Self {
+        Self {
+            meter,
+            symbol_ticks,
+            total: 0,
+            ticks: 0,
+            bar: 1,
+        }
     }
 
-    Ok(elements)
+    fn add(&mut self, element: &NoteElement) {
+        let ticks = if element.has_length() {
+            self.symbol_ticks
+        } else {
+            0
+        };
+        self.total += ticks;
+        self.ticks += ticks;
+    }
+
+    /// End the current bar, with a message if its length is wrong
+    fn bar_line(&mut self) -> Option<String> {
+        let full = self.meter.bar_ticks();
+        // The first bar may be a pickup, or empty before a leading bar line
+        let wrong = if self.bar == 1 {
+            self.ticks > full
+        } else {
+            self.ticks != full
+        };
+        let message = wrong.then(|| self.message());
+        self.bar += 1;
+        self.ticks = 0;
+        message
+    }
+
+    /// A message if the last bar is longer than a bar of the meter; without
+    /// any bar lines nothing is checked
+    fn finish(&self) -> Option<String> {
+        (self.bar > 1 && self.ticks > self.meter.bar_ticks()).then(|| self.message())
+    }
+
+    fn message(&self) -> String {
+        format!(
+            "Bar {} is {} long, but a bar of {} is {}",
+            self.bar,
+            self.meter.format_beats(self.ticks),
+            self.meter,
+            self.meter.format_beats(self.meter.bar_ticks())
+        )
+    }
+}
+
+/// Write note elements back as notation, the inverse of `parse_note_notation`.
+/// Octave shifts are written only where the register changes, and the octave
+/// above the root is written as the position after the last scale note (`8`
+/// in a seven-note scale) when that is a single digit.
+/// Example: [Note(1,0), Note(2,1), Sustain, Rest, Note(1,0)] -> "1^2-.v1"
+pub fn format_note_notation(
+    elements: &[NoteElement],
+    scale_len: usize,
+) -> Result<String, SoundError> {
+    Ok(notation_symbols(elements, scale_len)?.concat())
+}
+
+/// Write several voices as lines of notation that line up column by column,
+/// the way voices of a score are aligned by hand: every element gets a
+/// column as wide as its widest symbol, with octave shifts hanging to the
+/// left of the note, and bar lines line up bar by bar. `bar_length` replaces
+/// the voices' bar lines with one after every that many symbols.
+/// Example: [[Note(1,0), Note(2,0)], [Note(1,-1), Note(3,-1)]] -> [" 12", "v13"]
+pub fn format_aligned_notation(
+    voices: &[Vec<NoteElement>],
+    scale_len: usize,
+    bar_length: Option<usize>,
+) -> Result<Vec<String>, SoundError> {
+    // Each voice as its bars, each bar as its symbols
+    let voices = voices
+        .iter()
+        .map(|elements| {
+            let elements = match bar_length {
+                Some(length) if length > 0 => with_bar_lines(elements, length),
+                _ => elements.clone(),
+            };
+            let symbols = notation_symbols(&elements, scale_len)?;
+            Ok(symbols
+                .split(|symbol| symbol == "|")
+                .map(<[String]>::to_vec)
+                .collect::<Vec<_>>())
+        })
+        .collect::<Result<Vec<_>, SoundError>>()?;
+
+    let bar_count = voices.iter().map(Vec::len).max().unwrap_or(0);
+    let widths: Vec<Vec<usize>> = (0..bar_count)
+        .map(|bar| {
+            let columns = voices
+                .iter()
+                .filter_map(|bars| bars.get(bar).map(Vec::len))
+                .max()
+                .unwrap_or(0);
+            (0..columns)
+                .map(|column| {
+                    voices
+                        .iter()
+                        .filter_map(|bars| bars.get(bar)?.get(column).map(String::len))
+                        .max()
+                        .unwrap_or(1)
+                })
+                .collect()
+        })
+        .collect();
+
+    Ok(voices
+        .iter()
+        .map(|bars| {
+            let mut text = String::new();
+            for (bar, symbols) in bars.iter().enumerate() {
+                if bar > 0 {
+                    text.push('|');
+                }
+                for (column, symbol) in symbols.iter().enumerate() {
+                    text.push_str(&format!("{:>width$}", symbol, width = widths[bar][column]));
+                }
+                // Fill short bars so the next bar line lines up
+                if bar + 1 < bars.len() {
+                    let missing: usize = widths[bar].iter().skip(symbols.len()).sum();
+                    text.push_str(&" ".repeat(missing));
+                }
+            }
+            text
+        })
+        .collect())
+}
+
+/// The elements with their bar lines replaced by one after every `length`
+/// symbols
+pub(crate) fn with_bar_lines(elements: &[NoteElement], length: usize) -> Vec<NoteElement> {
+    let mut result = Vec::new();
+    let mut count = 0;
+    for element in elements {
+        if matches!(element, NoteElement::Bar) {
+            continue;
+        }
+        if element.has_length() {
+            if count > 0 && count % length == 0 {
+                result.push(NoteElement::Bar);
+            }
+            count += 1;
+        }
+        result.push(element.clone());
+    }
+    result
+}
+
+/// One symbol per bar line and per element that takes time, with octave
+/// shifts written before it and ties and articulation marks after it
+fn notation_symbols(elements: &[NoteElement], scale_len: usize) -> Result<Vec<String>, SoundError> {
+    let mut symbols: Vec<String> = Vec::new();
+    let mut register = 0i32;
+    // Marks with no symbol before them in the bar, written before the next
+    let mut marks = String::new();
+
+    for element in elements {
+        let symbol = match element {
+            NoteElement::Note(position, octave) => {
+                let (position, octave) =
+                    if *position == 1 && *octave == register + 1 && scale_len < 9 {
+                        (scale_len + 1, register)
+                    } else {
+                        (*position, *octave)
+                    };
+                if position > 9 {
+                    return Err(SoundError::OutOfRange(format!(
+                        "Note position {} cannot be written in notation (digits 1-9 only)",
+                        position
+                    )));
+                }
+
+                let shift = if octave > register { "^" } else { "v" };
+                let mut symbol = shift.repeat((octave - register).unsigned_abs() as usize);
+                register = octave;
+                symbol.push(char::from_digit(position as u32, 10).unwrap());
+                symbol
+            }
+            NoteElement::Rest => ".".to_string(),
+            NoteElement::Sustain => "-".to_string(),
+            NoteElement::Bar => {
+                if !marks.is_empty() {
+                    symbols.push(std::mem::take(&mut marks));
+                }
+                symbols.push("|".to_string());
+                continue;
+            }
+            NoteElement::Tie | NoteElement::Staccato | NoteElement::Legato => {
+                let mark = match element {
+                    NoteElement::Tie => '~',
+                    NoteElement::Staccato => '\'',
+                    _ => '_',
+                };
+                match symbols.last_mut() {
+                    Some(symbol) if symbol != "|" => symbol.push(mark),
+                    _ => marks.push(mark),
+                }
+                continue;
+            }
+        };
+        symbols.push(std::mem::take(&mut marks) + &symbol);
+    }
+    if !marks.is_empty() {
+        symbols.push(marks);
+    }
+
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_run_is_reported_once() {
+        let error = parse_note_notation(&["1 2 # my song 3".to_string()]).unwrap_err();
+        assert_eq!(
+            error.to_string().lines().count(),
+            1,
+            "expected one error, got {}",
+            error
+        );
+        let SoundError::Notation { column, length, .. } = error else {
+            panic!("expected a notation error, got {:?}", error);
+        };
+        assert_eq!((column, length), (5, 9));
+    }
+
+    #[test]
+    fn stray_colon_is_one_character() {
+        let error = parse_note_notation(&["1 : 2".to_string()]).unwrap_err();
+        assert!(matches!(
+            error,
+            SoundError::Notation {
+                column: 3,
+                length: 1,
+                ..
+            }
+        ));
+    }
 }